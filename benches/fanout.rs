@@ -0,0 +1,159 @@
+//! Throughput of delivering one room message to every connection in the room.
+//!
+//! Each connection is a fake session: a task that takes frames out of its outbox like the
+//! socket task in `routes::ws` does, without a socket behind it. Run with `cargo bench`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use diesel::{
+    connection::SimpleConnection,
+    r2d2::{ConnectionManager, Pool},
+    SqliteConnection,
+};
+use rust_react_chat::{
+    backplane::MemoryBackplane,
+    server::{self, ChatServer, ChatServerHandle, OverflowPolicy},
+    types::DbPool,
+    Msg,
+};
+use serde_json::json;
+use tokio::{runtime::Runtime, sync::Notify};
+
+const ROOM_ID: &str = "bench-room";
+
+/// A database with the schema applied and `sessions` users in one room.
+fn bench_pool(path: &Path, sessions: usize) -> DbPool {
+    let _ = fs::remove_file(path);
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(ConnectionManager::<SqliteConnection>::new(
+            path.to_string_lossy(),
+        ))
+        .expect("failed to open the benchmark database");
+    let mut conn = pool.get().unwrap();
+
+    let mut migrations: Vec<PathBuf> = fs::read_dir("migrations")
+        .expect("run the benchmarks from the crate root")
+        .map(|entry| entry.unwrap().path().join("up.sql"))
+        .filter(|up| up.exists())
+        .collect();
+    migrations.sort();
+
+    for migration in migrations {
+        conn.batch_execute(&fs::read_to_string(migration).unwrap())
+            .unwrap();
+    }
+
+    conn.batch_execute(&format!(
+        "INSERT INTO rooms (id, name, last_message, created_at, owner_id)
+         VALUES ('{ROOM_ID}', 'bench', '', '', 'user-1');
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {sessions})
+         INSERT INTO rooms_users (room_id, user_id, role)
+         SELECT '{ROOM_ID}', 'user-' || i, 'member' FROM n;"
+    ))
+    .unwrap();
+
+    drop(conn);
+    pool
+}
+
+/// Start a chat server with `sessions` fake sessions in the bench room. `received` counts the
+/// frames they take out, and `done` is notified every time all of them got one more frame.
+fn start(
+    rt: &Runtime,
+    pool: DbPool,
+    sessions: usize,
+    received: Arc<AtomicUsize>,
+    done: Arc<Notify>,
+) -> ChatServerHandle {
+    rt.block_on(async {
+        let (shards, handle) = ChatServer::new(pool, 1, 1000, Arc::new(MemoryBackplane::new()));
+        for shard in shards {
+            tokio::spawn(shard.run());
+        }
+
+        for i in 1..=sessions {
+            let (outbox, receiver) = server::outbox(64, OverflowPolicy::DropOldest);
+            handle
+                .connect(outbox, format!("user-{i}"))
+                .await
+                .expect("failed to connect a fake session");
+
+            let received = received.clone();
+            let done = done.clone();
+            tokio::spawn(async move {
+                while receiver.recv().await.is_some() {
+                    if (received.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(sessions) {
+                        done.notify_one();
+                    }
+                }
+            });
+        }
+
+        handle
+    })
+}
+
+fn fanout(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let frame: Msg = json!({
+        "type": "message",
+        "data": {
+            "room_id": ROOM_ID,
+            "user_id": "user-1",
+            "content": "The quick brown fox jumps over the lazy dog. ".repeat(4),
+        }
+    })
+    .to_string()
+    .into();
+
+    let mut group = c.benchmark_group("fanout");
+
+    for sessions in [10, 1_000, 10_000] {
+        let path = std::env::temp_dir().join(format!(
+            "rust-react-chat-fanout-{}-{sessions}.db",
+            std::process::id()
+        ));
+        let received = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(Notify::new());
+        let handle = start(
+            &rt,
+            bench_pool(&path, sessions),
+            sessions,
+            received,
+            done.clone(),
+        );
+
+        group.throughput(Throughput::Elements(sessions as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(sessions), &sessions, |b, _| {
+            b.iter(|| {
+                rt.block_on(async {
+                    handle
+                        .send_message(frame.clone(), ROOM_ID.to_string(), 0)
+                        .await;
+                    done.notified().await;
+                })
+            })
+        });
+
+        let _ = fs::remove_file(&path);
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
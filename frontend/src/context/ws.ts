@@ -76,6 +76,21 @@ const wsDeleteRoomSchema = z.object({
   data: z.object({ room_id: z.string() }),
 });
 
+// owners "delete" a room by archiving it; archived rooms leave the room list until restored
+const wsArchiveRoomSchema = z.object({
+  type: z.literal('archive_room'),
+  data: z.object({
+    room: z.object({ id: z.string() }),
+  }),
+});
+
+const wsUnarchiveRoomSchema = z.object({
+  type: z.literal('unarchive_room'),
+  data: z.object({
+    room: z.object({ id: z.string() }),
+  }),
+});
+
 const wsJoinRoomSchema = z.object({
   type: z.literal('join_room'),
   data: z.object({
@@ -97,6 +112,8 @@ export const wsSchema = z.discriminatedUnion('type', [
   wsCreateMessageSchema,
   wsCreateRoomSchema,
   wsDeleteRoomSchema,
+  wsArchiveRoomSchema,
+  wsUnarchiveRoomSchema,
   wsJoinRoomSchema,
   wsExitRoomSchema,
 ]);
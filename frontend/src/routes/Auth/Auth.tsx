@@ -1,16 +1,18 @@
 import { Checkbox, TextInput } from '@mantine/core';
 import { BaseError, User } from '@types';
 import { Dispatch, SetStateAction, useEffect, useState } from 'react';
-import { ActionFunctionArgs, redirect, useFetcher, useNavigate } from 'react-router-dom';
+import { ActionFunctionArgs, FetcherWithComponents, redirect, useFetcher, useNavigate } from 'react-router-dom';
 import { z } from 'zod';
 import * as _ from 'radash';
 
 const ACTION_TYPES = {
   SIGNUP: 'signup',
   SIGNIN: 'signin',
+  SIGNIN_2FA: 'signin_2fa',
 };
 
-type ActionData = { status: number; data: BaseError | User; sign_in?: boolean };
+// two_factor is set while the sign in waits for a two-factor code
+type ActionData = { status: number; data: BaseError | User; sign_in?: boolean; two_factor?: boolean };
 
 const signupSchema = z
   .object({
@@ -27,10 +29,54 @@ const signupSchema = z
     sign_in: data.signin,
   }));
 
+const twoFactorSchema = z.object({
+  type: z.literal(ACTION_TYPES.SIGNIN_2FA),
+  code: z.string().trim(),
+});
+
+// recovery codes look like xxxxx-xxxxx, authenticator codes are 6 digits
+const RECOVERY_CODE = /[a-z]/i;
+
+async function verifyTwoFactor(code: string): Promise<ActionData | Response> {
+  const res = await fetch('/api/auth/signin/2fa', {
+    method: 'post',
+    body: JSON.stringify(RECOVERY_CODE.test(code) ? { recovery_code: code } : { code }),
+    headers: {
+      'Content-Type': 'application/json',
+    },
+  });
+
+  if (res.status === 200) {
+    return redirect('/');
+  }
+
+  return {
+    status: res.status,
+    data: await res.json(),
+    two_factor: true,
+  };
+}
+
 export async function action({ request }: ActionFunctionArgs): Promise<ActionData | Response> {
   const formData = await request.formData();
   const formDataObj = Object.fromEntries(formData);
 
+  if (formDataObj.type === ACTION_TYPES.SIGNIN_2FA) {
+    const result = twoFactorSchema.safeParse(formDataObj);
+    if (!result.success) {
+      return {
+        status: 400,
+        data: {
+          success: false,
+          message: 'Invalid form data',
+        },
+        two_factor: true,
+      };
+    }
+
+    return verifyTwoFactor(result.data.code);
+  }
+
   const result = signupSchema.safeParse(formDataObj);
   if (!result.success) {
     return {
@@ -68,14 +114,19 @@ export async function action({ request }: ActionFunctionArgs): Promise<ActionDat
         },
       });
 
-      if (res.status === 200) {
-        return redirect('/');
+      if (res.status !== 200) {
+        return {
+          status: res.status,
+          data: await res.json(),
+        };
       }
 
-      return {
-        status: res.status,
-        data: await res.json(),
-      };
+      const data = await res.json();
+      if (data.two_factor_required) {
+        return { status: res.status, data, two_factor: true };
+      }
+
+      return redirect('/');
     }
   }
   throw new Error('Invalid action type');
@@ -143,8 +194,51 @@ const FormSignUp = ({ setShowSignIn }: { setShowSignIn: Dispatch<SetStateAction<
   );
 };
 
+const FormTwoFactor = ({ fetcher, onCancel }: { fetcher: FetcherWithComponents<ActionData>; onCancel: () => void }) => {
+  const error =
+    fetcher.data && fetcher.data.status !== 200 && 'message' in fetcher.data.data ? fetcher.data.data.message : null;
+
+  return (
+    <fetcher.Form method="post" className="mt-4 space-y-2">
+      <TextInput
+        label="Two-factor code"
+        description="Enter the code from your authenticator app, or one of your recovery codes."
+        required
+        type="text"
+        name="code"
+        radius="md"
+        placeholder="123456"
+        size="md"
+        autoComplete="one-time-code"
+        error={error}
+      />
+      <input type="hidden" name="type" value={ACTION_TYPES.SIGNIN_2FA} />
+
+      <div className="flex items-baseline justify-between">
+        <button type="submit" className="px-6 py-2 mt-4 text-white bg-violet-600 rounded-lg hover:bg-violet-700 w-full">
+          Verify
+        </button>
+      </div>
+      <div className="pt-2 space-y-2 text-center">
+        <button type="button" onClick={onCancel} className="text-violet-700 font-light">
+          Back to sign in
+        </button>
+      </div>
+    </fetcher.Form>
+  );
+};
+
 const FormSignIn = ({ setShowSignIn }: { setShowSignIn: Dispatch<SetStateAction<boolean>> }) => {
-  const fetcher = useFetcher();
+  const fetcher = useFetcher<ActionData>();
+  const [twoFactor, setTwoFactor] = useState(false);
+
+  useEffect(() => {
+    setTwoFactor(fetcher.data?.two_factor ?? false);
+  }, [fetcher.data]);
+
+  if (twoFactor) {
+    return <FormTwoFactor fetcher={fetcher} onCancel={() => setTwoFactor(false)} />;
+  }
 
   return (
     <fetcher.Form method="post" className="mt-4 space-y-2">
@@ -157,7 +251,9 @@ const FormSignIn = ({ setShowSignIn }: { setShowSignIn: Dispatch<SetStateAction<
         placeholder="Username"
         size="md"
         autoComplete="off"
-        error={fetcher.data && fetcher.data.data && 'message' in fetcher.data.data ? fetcher.data?.data.message : null}
+        error={
+          fetcher.data && !fetcher.data.two_factor && 'message' in fetcher.data.data ? fetcher.data.data.message : null
+        }
       />
 
       <TextInput
@@ -277,7 +277,13 @@ export default function Chat() {
       return;
     }
     const handleMessage = (event: MessageEvent) => {
-      const data = JSON.parse(event.data);
+      let data: unknown;
+      try {
+        data = JSON.parse(event.data);
+      } catch {
+        // replies to commands like /watch are plain text
+        return;
+      }
 
       const result = wsSchema.safeParse(data);
       if (!result.success) {
@@ -202,7 +202,13 @@ export default function ChatRoom() {
     const ws = wsContext.ws;
 
     const handleMessage = (event: MessageEvent) => {
-      const data = JSON.parse(event.data);
+      let data: unknown;
+      try {
+        data = JSON.parse(event.data);
+      } catch {
+        // replies to commands like /watch are plain text
+        return;
+      }
 
       const result = wsSchema.safeParse(data);
       if (!result.success) {
@@ -218,6 +224,8 @@ export default function ChatRoom() {
               conn_id,
             };
           });
+          // get room list events for the rooms we're not in too
+          ws.send('/watch');
           break;
         }
         case 'join_room': {
@@ -278,6 +286,22 @@ export default function ChatRoom() {
           });
           break;
         }
+
+        case 'archive_room': {
+          const { room } = result.data.data;
+          setRooms(prev => {
+            return prev.filter(r => r.room.id !== room.id);
+          });
+          break;
+        }
+
+        case 'unarchive_room': {
+          // the event doesn't carry the members, so load the list again
+          fetch('/api/rooms')
+            .then(res => res.json())
+            .then(setRooms);
+          break;
+        }
       }
     };
 
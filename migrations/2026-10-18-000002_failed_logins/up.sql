@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE failed_logins (
+  id TEXT PRIMARY KEY NOT NULL,
+  username VARCHAR NOT NULL,
+  user_id TEXT REFERENCES users(id),
+  ip TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
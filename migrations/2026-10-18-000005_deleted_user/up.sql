@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- placeholder author for conversations of deleted accounts; its empty password never verifies
+INSERT INTO users (id, username, password, created_at, display_name)
+VALUES ('00000000-0000-0000-0000-000000000000', 'deleted', '', '1970-01-01T00:00:00+00:00', 'Deleted user');
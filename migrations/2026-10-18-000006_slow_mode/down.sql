@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE rooms_users DROP COLUMN role;
+ALTER TABLE rooms DROP COLUMN slow_mode_seconds;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE rooms ADD COLUMN slow_mode_seconds INTEGER NOT NULL DEFAULT 0;
+
+-- 'member' or 'moderator'; the owner is tracked by rooms.owner_id
+ALTER TABLE rooms_users ADD COLUMN role TEXT NOT NULL DEFAULT 'member';
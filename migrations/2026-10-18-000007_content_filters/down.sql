@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE filter_decisions;
+DROP TABLE room_filters;
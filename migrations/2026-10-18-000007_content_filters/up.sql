@@ -0,0 +1,29 @@
+-- Your SQL goes here
+CREATE TABLE room_filters (
+    id TEXT PRIMARY KEY NOT NULL,
+    room_id TEXT NOT NULL REFERENCES rooms(id),
+    -- 'max_length', 'profanity', 'links' or 'regex'
+    kind TEXT NOT NULL,
+    -- length limit, comma separated word list or pattern, depending on kind
+    value TEXT NOT NULL,
+    -- 'reject', 'mask' or 'flag'
+    action TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX room_filters_room_id ON room_filters (room_id);
+
+CREATE TABLE filter_decisions (
+    id TEXT PRIMARY KEY NOT NULL,
+    room_id TEXT NOT NULL REFERENCES rooms(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    -- unset when the message was rejected
+    conversation_id TEXT REFERENCES conversations(id),
+    kind TEXT NOT NULL,
+    action TEXT NOT NULL,
+    -- the message as it was submitted, before masking
+    message TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX filter_decisions_room_id ON filter_decisions (room_id, created_at);
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+CREATE TABLE reports (
+    id TEXT PRIMARY KEY NOT NULL,
+    room_id TEXT NOT NULL REFERENCES rooms(id),
+    reporter_id TEXT NOT NULL REFERENCES users(id),
+    reported_user_id TEXT NOT NULL REFERENCES users(id),
+    -- unset when a user rather than a message is reported
+    conversation_id TEXT REFERENCES conversations(id),
+    reason TEXT NOT NULL,
+    -- 'open' or 'resolved'
+    status TEXT NOT NULL DEFAULT 'open',
+    -- 'dismiss', 'delete_message', 'kick' or 'ban' once resolved
+    resolution TEXT,
+    resolved_by TEXT REFERENCES users(id),
+    resolved_at TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX reports_room_id_status ON reports (room_id, status, created_at);
+
+CREATE TABLE room_bans (
+    room_id TEXT NOT NULL REFERENCES rooms(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    banned_by TEXT NOT NULL REFERENCES users(id),
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (room_id, user_id)
+);
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- append-only; ids are kept as plain text so entries outlive the rows they mention
+CREATE TABLE audit_events (
+    id TEXT PRIMARY KEY NOT NULL,
+    actor_id TEXT,
+    action TEXT NOT NULL,
+    room_id TEXT,
+    target_user_id TEXT,
+    -- JSON object
+    details TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX audit_events_room_id ON audit_events (room_id, created_at);
+
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT 0;
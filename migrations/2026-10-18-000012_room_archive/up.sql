@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- set while the room is archived: read-only and left out of the room list
+ALTER TABLE rooms ADD COLUMN archived_at TEXT;
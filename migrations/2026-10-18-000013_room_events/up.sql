@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- every event sent to a room, numbered per room, so reconnecting clients can catch up
+CREATE TABLE room_events (
+    room_id TEXT NOT NULL REFERENCES rooms(id),
+    seq BIGINT NOT NULL,
+    -- the user whose message it is, so replays skip users who blocked them
+    sender_id TEXT,
+    -- the frame as it was sent
+    payload TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    -- the message it carries, so deleting the message drops it from replays too
+    conversation_id TEXT,
+    PRIMARY KEY (room_id, seq)
+);
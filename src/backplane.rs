@@ -0,0 +1,125 @@
+//! Pub/sub between chat server processes.
+//!
+//! Every [`ChatServerHandle`](crate::server::ChatServerHandle) publishes the events that reach
+//! beyond a single connection, like room messages and blocks, to a [`Backplane`], and applies the
+//! events other processes published. [`MemoryBackplane`] keeps it all in one process;
+//! [`TcpBackplane`] goes through the broker in [`tcp::run_broker`], so several server processes
+//! can serve the same rooms.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{Msg, RoomId, UserId};
+
+pub mod tcp;
+
+pub use tcp::TcpBackplane;
+
+/// Events a backplane subscriber may fall behind by before it starts missing them.
+const EVENT_BUFFER: usize = 1024;
+
+/// Identifies the chat server process that published an event.
+pub type NodeId = u64;
+
+/// Something every process needs to apply to its own connections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A message for the connections in a room.
+    Message {
+        room_id: RoomId,
+        /// The user who wrote the message, if it is a user's message.
+        sender: Option<UserId>,
+        msg: Msg,
+    },
+    /// A message for the connections in any of the rooms.
+    RoomsMessage {
+        rooms: Vec<RoomId>,
+        msg: Msg,
+    },
+    /// A message for every connection.
+    Broadcast {
+        msg: Msg,
+    },
+    /// A message for the connections in a room and the ones watching the room directory.
+    Announce {
+        room_id: RoomId,
+        msg: Msg,
+    },
+    /// Like `Announce`, for a room that was deleted and is forgotten afterwards.
+    RoomClosed {
+        room_id: RoomId,
+        msg: Msg,
+    },
+    /// A user joined a room, so their connections are in it now.
+    MemberAdded {
+        room_id: RoomId,
+        user_id: UserId,
+    },
+    /// A user left or was removed from a room.
+    MemberRemoved {
+        room_id: RoomId,
+        user_id: UserId,
+    },
+    Block {
+        blocker: UserId,
+        blocked: UserId,
+    },
+    Unblock {
+        blocker: UserId,
+        blocked: UserId,
+    },
+    /// Close every connection of a user.
+    DisconnectUser {
+        user_id: UserId,
+    },
+}
+
+/// An event and the process that published it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub node: NodeId,
+    pub event: Event,
+}
+
+pub trait Backplane: Send + Sync + fmt::Debug {
+    /// Send an event to the subscribers in every process. Subscribers in the publishing process
+    /// may get it back too; they tell by its `node`.
+    fn publish(&self, envelope: Envelope);
+
+    /// Receive the events published from now on.
+    fn subscribe(&self) -> broadcast::Receiver<Envelope>;
+}
+
+/// A backplane within one process, for running a single server.
+#[derive(Debug)]
+pub struct MemoryBackplane {
+    events: broadcast::Sender<Envelope>,
+}
+
+impl MemoryBackplane {
+    pub fn new() -> Self {
+        Self {
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl Default for MemoryBackplane {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backplane for MemoryBackplane {
+    fn publish(&self, envelope: Envelope) {
+        // nobody subscribed yet is fine
+        let _ = self.events.send(envelope);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.events.subscribe()
+    }
+}
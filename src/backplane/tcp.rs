@@ -0,0 +1,168 @@
+//! A backplane over plain TCP.
+//!
+//! Server processes connect to a broker and exchange newline-delimited JSON [`Envelope`]s. The
+//! broker relays every line it gets to every other connected process. There is no
+//! authentication, so keep the broker on a private interface.
+
+use std::{io, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+    time::sleep,
+};
+
+use super::{Backplane, Envelope, EVENT_BUFFER};
+
+/// Events waiting to be written to the broker before new ones are dropped.
+const OUTGOING_BUFFER: usize = 1024;
+
+/// How long to wait before reconnecting to the broker.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A backplane connected to a broker started with [`run_broker`].
+#[derive(Debug)]
+pub struct TcpBackplane {
+    outgoing: mpsc::Sender<String>,
+    events: broadcast::Sender<Envelope>,
+}
+
+impl TcpBackplane {
+    /// Connect to the broker at `addr` in the background, reconnecting whenever the connection
+    /// drops. Events published while disconnected are queued until the queue is full.
+    pub fn connect(addr: String) -> Self {
+        let (outgoing, outgoing_rx) = mpsc::channel(OUTGOING_BUFFER);
+        let events = broadcast::channel(EVENT_BUFFER).0;
+
+        tokio::spawn(maintain(addr, outgoing_rx, events.clone()));
+
+        Self { outgoing, events }
+    }
+}
+
+impl Backplane for TcpBackplane {
+    fn publish(&self, envelope: Envelope) {
+        let line = match serde_json::to_string(&envelope) {
+            Ok(line) => line + "\n",
+            Err(err) => {
+                log::error!("failed to serialize backplane event: {err}");
+                return;
+            }
+        };
+
+        if self.outgoing.try_send(line).is_err() {
+            log::warn!("backplane queue is full; dropping event");
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.events.subscribe()
+    }
+}
+
+/// Keep a connection to the broker, writing outgoing events and publishing incoming ones to
+/// `events`. Returns once the [`TcpBackplane`] is dropped.
+async fn maintain(
+    addr: String,
+    mut outgoing: mpsc::Receiver<String>,
+    events: broadcast::Sender<Envelope>,
+) {
+    loop {
+        match TcpStream::connect(&addr).await {
+            Ok(stream) => {
+                log::info!("connected to backplane broker at {addr}");
+
+                let (reader, mut writer) = stream.into_split();
+                let mut incoming = BufReader::new(reader).lines();
+
+                loop {
+                    tokio::select! {
+                        line = incoming.next_line() => match line {
+                            Ok(Some(line)) => match serde_json::from_str(&line) {
+                                Ok(envelope) => {
+                                    let _ = events.send(envelope);
+                                }
+                                Err(err) => log::warn!("ignoring invalid backplane event: {err}"),
+                            },
+                            Ok(None) => break,
+                            Err(err) => {
+                                log::warn!("failed to read from backplane broker: {err}");
+                                break;
+                            }
+                        },
+
+                        line = outgoing.recv() => match line {
+                            Some(line) => {
+                                if let Err(err) = writer.write_all(line.as_bytes()).await {
+                                    log::warn!("failed to write to backplane broker: {err}");
+                                    break;
+                                }
+                            }
+                            None => return,
+                        },
+                    }
+                }
+
+                log::warn!("lost connection to backplane broker at {addr}");
+            }
+            Err(err) => log::warn!("can't reach backplane broker at {addr}: {err}"),
+        }
+
+        sleep(RECONNECT_INTERVAL).await;
+    }
+}
+
+/// Run a broker on `addr`, relaying every line a client sends to all other clients.
+pub async fn run_broker(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let (lines, _) = broadcast::channel(EVENT_BUFFER);
+    let mut next_client = 0;
+
+    log::info!("backplane broker listening on {addr}");
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                next_client += 1;
+                log::info!("client {next_client} connected from {peer}");
+
+                tokio::spawn(relay(next_client, stream, lines.clone()));
+            }
+            Err(err) => log::warn!("failed to accept a client: {err}"),
+        }
+    }
+}
+
+/// Serve one broker client: publish its lines to `lines` and write it everyone else's.
+async fn relay(client: usize, stream: TcpStream, lines: broadcast::Sender<(usize, Arc<str>)>) {
+    let mut others = lines.subscribe();
+    let (reader, mut writer) = stream.into_split();
+    let mut incoming = BufReader::new(reader).lines();
+
+    loop {
+        tokio::select! {
+            line = incoming.next_line() => match line {
+                Ok(Some(line)) => {
+                    let _ = lines.send((client, format!("{line}\n").into()));
+                }
+                _ => break,
+            },
+
+            line = others.recv() => match line {
+                Ok((from, line)) if from != client => {
+                    if writer.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("client {client} fell behind and missed {missed} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+
+    log::info!("client {client} disconnected");
+}
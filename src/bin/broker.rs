@@ -0,0 +1,20 @@
+//! Backplane broker for running several chat server processes together.
+//!
+//! Start it with `cargo run --bin broker [addr]` (default `127.0.0.1:7070`) and point every server
+//! at it with `BACKPLANE_ADDR`.
+
+use std::{env, io};
+
+use env_logger::Env;
+use rust_react_chat::backplane::tcp::run_broker;
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
+
+    let addr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7070".to_string());
+
+    run_broker(&addr).await
+}
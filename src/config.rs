@@ -0,0 +1,264 @@
+use std::{env, str::FromStr, thread};
+
+use crate::{
+    filters::{FilterAction, FilterKind, FilterRule},
+    rate_limit::RateLimit,
+    resume,
+    server::OverflowPolicy,
+};
+
+/// Runtime configuration, read from environment variables with sensible defaults.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub password: PasswordConfig,
+    pub login: LoginConfig,
+    pub two_factor: TwoFactorConfig,
+    pub rate_limit: RateLimitConfig,
+    pub filters: FilterConfig,
+    pub admin: AdminConfig,
+    pub socket: SocketConfig,
+    pub chat: ChatConfig,
+    pub resume: ResumeConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordConfig {
+    /// bcrypt cost used for new hashes. Hashes with a different cost are upgraded on sign in.
+    pub bcrypt_cost: u32,
+
+    /// Minimum number of characters a password must have.
+    pub min_length: usize,
+
+    /// How long a password reset token stays valid, in minutes.
+    pub reset_token_ttl_minutes: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginConfig {
+    /// Failed attempts allowed before backoff kicks in.
+    pub free_attempts: u32,
+
+    /// Delay after the first failure past `free_attempts`, doubled for every further failure.
+    pub base_backoff_secs: u64,
+
+    /// Upper bound for the exponential backoff delay.
+    pub max_backoff_secs: u64,
+
+    /// Failed attempts after which the account or IP is locked out.
+    pub lockout_threshold: u32,
+
+    /// How long a lockout lasts.
+    pub lockout_secs: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TwoFactorConfig {
+    /// Issuer shown in authenticator apps.
+    pub issuer: String,
+
+    /// Number of recovery codes handed out when two-factor is enabled.
+    pub recovery_code_count: usize,
+
+    /// How long a sign in may wait for its second factor, in seconds.
+    pub pending_ttl_secs: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Messages a user may send, across all of their connections.
+    pub messages_per_user: RateLimit,
+
+    /// Messages a single connection may send.
+    pub messages_per_connection: RateLimit,
+
+    /// Rooms a user may create.
+    pub room_creation: RateLimit,
+
+    /// Requests to the sign up, sign in and password reset endpoints per client IP.
+    pub auth_per_ip: RateLimit,
+
+    /// Text frames a WebSocket connection may send.
+    pub socket_frames: RateLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    /// Username promoted to site admin at startup while the site has no admin. Empty to skip.
+    pub bootstrap_username: String,
+
+    /// Days a room has to stay archived before an admin can delete it for good.
+    pub room_delete_grace_days: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SocketConfig {
+    /// Frames queued for a WebSocket connection before `overflow_policy` applies.
+    pub queue_capacity: usize,
+
+    /// What happens to frames for a connection whose queue is full.
+    pub overflow_policy: OverflowPolicy,
+
+    /// How often the server pings each connection, in seconds.
+    pub heartbeat_interval_secs: u64,
+
+    /// How long a connection may stay silent before it is closed, in seconds.
+    pub client_timeout_secs: u64,
+
+    /// Also send `ping` text frames, for clients behind proxies that swallow WebSocket pings.
+    pub app_ping: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatConfig {
+    /// Port the HTTP server listens on.
+    pub port: u16,
+
+    /// Number of chat server shards rooms are split across.
+    pub shards: usize,
+
+    /// Address of the backplane broker shared with other server processes. Empty to run alone.
+    pub backplane_addr: String,
+
+    /// How long a graceful shutdown may take before the server exits anyway, in seconds.
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResumeConfig {
+    /// Key resume tokens are signed with. Processes sharing a backplane need the same one.
+    pub token_secret: String,
+
+    /// How long a resume token stays valid after it is handed out, in seconds.
+    pub token_ttl_secs: u64,
+
+    /// Most events replayed per room when a client resumes. Rooms further behind are refetched.
+    pub max_replay: i64,
+
+    /// Events kept per room for replays.
+    pub event_retention: i64,
+}
+
+/// Message filters applied in every room, before the room's own rules.
+#[derive(Debug, Clone)]
+pub struct FilterConfig {
+    /// Messages longer than this many characters are rejected. `0` disables the check.
+    pub max_message_length: usize,
+
+    /// Comma separated words handled with `profanity_action`. Empty disables the check.
+    pub profanity_words: String,
+
+    pub profanity_action: FilterAction,
+
+    /// Pattern handled with `regex_action`. Empty disables the check.
+    pub regex: String,
+
+    pub regex_action: FilterAction,
+}
+
+impl FilterConfig {
+    pub fn rules(&self) -> Vec<FilterRule> {
+        let mut rules = Vec::new();
+
+        if self.max_message_length > 0 {
+            rules.push(FilterRule::new(
+                FilterKind::MaxLength,
+                self.max_message_length.to_string(),
+                FilterAction::Reject,
+            ));
+        }
+        if !self.profanity_words.trim().is_empty() {
+            rules.push(FilterRule::new(
+                FilterKind::Profanity,
+                self.profanity_words.clone(),
+                self.profanity_action,
+            ));
+        }
+        if !self.regex.is_empty() {
+            rules.push(FilterRule::new(
+                FilterKind::Regex,
+                self.regex.clone(),
+                self.regex_action,
+            ));
+        }
+
+        rules
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            password: PasswordConfig {
+                bcrypt_cost: env_or("BCRYPT_COST", bcrypt::DEFAULT_COST),
+                min_length: env_or("PASSWORD_MIN_LENGTH", 8),
+                reset_token_ttl_minutes: env_or("PASSWORD_RESET_TTL_MINUTES", 30),
+            },
+            login: LoginConfig {
+                free_attempts: env_or("LOGIN_FREE_ATTEMPTS", 3),
+                base_backoff_secs: env_or("LOGIN_BASE_BACKOFF_SECS", 1),
+                max_backoff_secs: env_or("LOGIN_MAX_BACKOFF_SECS", 60),
+                lockout_threshold: env_or("LOGIN_LOCKOUT_THRESHOLD", 10),
+                lockout_secs: env_or("LOGIN_LOCKOUT_SECS", 15 * 60),
+            },
+            two_factor: TwoFactorConfig {
+                issuer: env_or("TOTP_ISSUER", "rust-react-chat".to_string()),
+                recovery_code_count: env_or("TOTP_RECOVERY_CODES", 10),
+                pending_ttl_secs: env_or("TOTP_PENDING_TTL_SECS", 5 * 60),
+            },
+            rate_limit: RateLimitConfig {
+                messages_per_user: env_or("RATE_LIMIT_MESSAGES_USER", RateLimit::new(20, 10)),
+                messages_per_connection: env_or(
+                    "RATE_LIMIT_MESSAGES_CONNECTION",
+                    RateLimit::new(10, 10),
+                ),
+                room_creation: env_or("RATE_LIMIT_ROOM_CREATION", RateLimit::new(5, 60)),
+                auth_per_ip: env_or("RATE_LIMIT_AUTH", RateLimit::new(20, 60)),
+                socket_frames: env_or("RATE_LIMIT_SOCKET_FRAMES", RateLimit::new(30, 10)),
+            },
+            filters: FilterConfig {
+                max_message_length: env_or("FILTER_MAX_LENGTH", 2000),
+                profanity_words: env_or("FILTER_PROFANITY_WORDS", String::new()),
+                profanity_action: env_or("FILTER_PROFANITY_ACTION", FilterAction::Mask),
+                regex: env_or("FILTER_REGEX", String::new()),
+                regex_action: env_or("FILTER_REGEX_ACTION", FilterAction::Flag),
+            },
+            admin: AdminConfig {
+                bootstrap_username: env_or("ADMIN_BOOTSTRAP_USERNAME", String::new()),
+                room_delete_grace_days: env_or("ROOM_DELETE_GRACE_DAYS", 30),
+            },
+            socket: SocketConfig {
+                queue_capacity: env_or("SOCKET_QUEUE_CAPACITY", 256),
+                overflow_policy: env_or("SOCKET_OVERFLOW_POLICY", OverflowPolicy::DropConnection),
+                heartbeat_interval_secs: env_or("SOCKET_HEARTBEAT_INTERVAL_SECS", 5),
+                client_timeout_secs: env_or("SOCKET_CLIENT_TIMEOUT_SECS", 10),
+                app_ping: env_or("SOCKET_APP_PING", false),
+            },
+            chat: ChatConfig {
+                port: env_or("PORT", 8080),
+                shards: env_or(
+                    "CHAT_SERVER_SHARDS",
+                    thread::available_parallelism().map_or(1, |cores| cores.get()),
+                ),
+                backplane_addr: env_or("BACKPLANE_ADDR", String::new()),
+                shutdown_timeout_secs: env_or("SHUTDOWN_TIMEOUT_SECS", 10),
+            },
+            resume: ResumeConfig {
+                token_secret: env_or("RESUME_TOKEN_SECRET", resume::generate_secret()),
+                token_ttl_secs: env_or("RESUME_TOKEN_TTL_SECS", 12 * 60 * 60),
+                max_replay: env_or("RESUME_MAX_EVENTS", 500),
+                event_retention: env_or("ROOM_EVENT_RETENTION", 1000),
+            },
+        }
+    }
+}
+
+/// Read `key` from the environment, falling back to `default` when unset or unparsable.
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            log::warn!("invalid value {value:?} for {key}, using default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
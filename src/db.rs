@@ -1,14 +1,32 @@
-use crate::models::{Conversation, NewConversation, Room, User};
-use bcrypt::{hash, DEFAULT_COST};
+use crate::models::{Conversation, NewConversation};
 use chrono::{DateTime, Utc};
-use diesel::prelude::*;
-use std::{
-    collections::{HashMap, HashSet},
-    time::SystemTime,
+use diesel::{
+    connection::SimpleConnection,
+    prelude::*,
+    r2d2::{self, CustomizeConnection},
 };
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 pub type DbError = Box<dyn std::error::Error + Send + Sync>;
 
+/// How long a connection waits for another one to finish writing before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sets up every connection of the pool. Without a busy timeout, SQLite fails a write right
+/// away while another connection is writing, instead of letting them take turns.
+#[derive(Debug)]
+pub struct ConnectionOptions;
+
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        conn.batch_execute(&format!(
+            "PRAGMA busy_timeout = {};",
+            BUSY_TIMEOUT.as_millis()
+        ))
+        .map_err(r2d2::Error::QueryError)
+    }
+}
+
 fn iso_date() -> String {
     let now = SystemTime::now();
     let now: DateTime<Utc> = now.into();
@@ -48,7 +66,16 @@ pub fn insert_new_conversation(
     Ok(new_conversation)
 }
 
+pub mod audit;
+pub mod blocks;
 pub mod conversations;
+pub mod failed_logins;
+pub mod filters;
+pub mod password_resets;
+pub mod reports;
+pub mod room_events;
 pub mod rooms;
 pub mod rooms_users;
+pub mod stats;
+pub mod two_factor;
 pub mod users;
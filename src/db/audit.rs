@@ -0,0 +1,94 @@
+use diesel::prelude::*;
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::AuditEvent;
+
+use super::{iso_date, DbError};
+
+/// Entries returned when no limit is given.
+const DEFAULT_LIMIT: i64 = 50;
+
+/// Most entries returned by one query.
+const MAX_LIMIT: i64 = 200;
+
+/// Filters for listing audit events. Every field that is set must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    /// An action such as `room.join`, or a prefix ending in `.` such as `report.`.
+    pub action: Option<String>,
+    pub actor_id: Option<String>,
+    pub target_user_id: Option<String>,
+    pub room_id: Option<String>,
+    /// Only events before this timestamp, for paging.
+    pub before: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Append an entry to the audit log. Entries are never updated or deleted.
+pub fn record(
+    conn: &mut SqliteConnection,
+    actor_id: Option<&str>,
+    action: &str,
+    room_id: Option<&str>,
+    target_user_id: Option<&str>,
+    details: Value,
+) -> Result<AuditEvent, DbError> {
+    use crate::schema::audit_events;
+
+    let event = AuditEvent {
+        id: Uuid::new_v4().to_string(),
+        actor_id: actor_id.map(str::to_string),
+        action: action.to_string(),
+        room_id: room_id.map(str::to_string),
+        target_user_id: target_user_id.map(str::to_string),
+        details: details.to_string(),
+        created_at: iso_date(),
+    };
+
+    diesel::insert_into(audit_events::table)
+        .values(&event)
+        .execute(conn)?;
+
+    Ok(event)
+}
+
+/// Audit events matching `query`, newest first.
+pub fn list_events(
+    conn: &mut SqliteConnection,
+    query: &AuditQuery,
+) -> Result<Vec<AuditEvent>, DbError> {
+    use crate::schema::audit_events;
+
+    let mut select = audit_events::table.into_boxed();
+
+    if let Some(action) = &query.action {
+        select = if action.ends_with('.') {
+            // `_` and `%` don't appear in action names, so the prefix needs no escaping
+            select.filter(audit_events::action.like(format!("{action}%")))
+        } else {
+            select.filter(audit_events::action.eq(action))
+        };
+    }
+    if let Some(actor_id) = &query.actor_id {
+        select = select.filter(audit_events::actor_id.eq(actor_id));
+    }
+    if let Some(target_user_id) = &query.target_user_id {
+        select = select.filter(audit_events::target_user_id.eq(target_user_id));
+    }
+    if let Some(room_id) = &query.room_id {
+        select = select.filter(audit_events::room_id.eq(room_id));
+    }
+    if let Some(before) = &query.before {
+        select = select.filter(audit_events::created_at.lt(before));
+    }
+
+    let events = select
+        .order(audit_events::created_at.desc())
+        .limit(query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+        .select(AuditEvent::as_select())
+        .load(conn)?;
+
+    Ok(events)
+}
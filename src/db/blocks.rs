@@ -0,0 +1,78 @@
+use diesel::prelude::*;
+
+use crate::models::{User, UserBlock};
+
+use super::{iso_date, DbError};
+
+/// Users blocked by `blocker_id`, most recently blocked first.
+pub fn list_blocked_users(
+    conn: &mut SqliteConnection,
+    blocker_id: &str,
+) -> Result<Vec<User>, DbError> {
+    use crate::schema::{user_blocks, users};
+
+    let users = user_blocks::table
+        .filter(user_blocks::blocker_id.eq(blocker_id))
+        .inner_join(users::table.on(users::id.eq(user_blocks::blocked_id)))
+        .order(user_blocks::created_at.desc())
+        .select(User::as_select())
+        .load(conn)?;
+
+    Ok(users)
+}
+
+/// Ids of the users blocked by `blocker_id`.
+pub fn blocked_ids(conn: &mut SqliteConnection, blocker_id: &str) -> Result<Vec<String>, DbError> {
+    use crate::schema::user_blocks;
+
+    let ids = user_blocks::table
+        .filter(user_blocks::blocker_id.eq(blocker_id))
+        .select(user_blocks::blocked_id)
+        .load(conn)?;
+
+    Ok(ids)
+}
+
+/// Every block on the site, as `(blocker_id, blocked_id)` pairs.
+pub fn all_blocks(conn: &mut SqliteConnection) -> Result<Vec<(String, String)>, DbError> {
+    use crate::schema::user_blocks;
+
+    let blocks = user_blocks::table
+        .select((user_blocks::blocker_id, user_blocks::blocked_id))
+        .load(conn)?;
+
+    Ok(blocks)
+}
+
+/// Block a user. Blocking someone twice is not an error.
+pub fn block(
+    conn: &mut SqliteConnection,
+    blocker_id: &str,
+    blocked_id: &str,
+) -> Result<(), DbError> {
+    use crate::schema::user_blocks;
+
+    diesel::insert_or_ignore_into(user_blocks::table)
+        .values(&UserBlock {
+            blocker_id: blocker_id.to_string(),
+            blocked_id: blocked_id.to_string(),
+            created_at: iso_date(),
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Returns `false` if the user wasn't blocked.
+pub fn unblock(
+    conn: &mut SqliteConnection,
+    blocker_id: &str,
+    blocked_id: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::user_blocks;
+
+    let deleted =
+        diesel::delete(user_blocks::table.find((blocker_id, blocked_id))).execute(conn)?;
+
+    Ok(deleted == 1)
+}
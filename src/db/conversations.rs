@@ -1,4 +1,4 @@
-use diesel::{RunQueryDsl, SqliteConnection};
+use diesel::prelude::*;
 use uuid::Uuid;
 
 use crate::{db::iso_date, models::Conversation};
@@ -27,3 +27,49 @@ pub fn create_conversation(
 
     Ok(new_conversation)
 }
+
+/// When the user last posted in the room, if ever.
+pub fn last_message_at(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    user_id: &str,
+) -> Result<Option<String>, DbError> {
+    use crate::schema::conversations;
+
+    let created_at = conversations::table
+        .filter(conversations::room_id.eq(room_id))
+        .filter(conversations::user_id.eq(user_id))
+        .select(conversations::created_at)
+        .order(conversations::created_at.desc())
+        .first(conn)
+        .optional()?;
+
+    Ok(created_at)
+}
+
+pub fn find_conversation(
+    conn: &mut SqliteConnection,
+    conversation_id: &str,
+) -> Result<Option<Conversation>, DbError> {
+    use crate::schema::conversations;
+
+    let conversation = conversations::table
+        .find(conversation_id)
+        .select(Conversation::as_select())
+        .first(conn)
+        .optional()?;
+
+    Ok(conversation)
+}
+
+pub fn delete_conversation(
+    conn: &mut SqliteConnection,
+    conversation_id: &str,
+) -> Result<(), DbError> {
+    use crate::schema::conversations;
+
+    super::room_events::delete_for_conversation(conn, conversation_id)?;
+    diesel::delete(conversations::table.find(conversation_id)).execute(conn)?;
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::models::FailedLogin;
+
+use super::{iso_date, DbError};
+
+pub fn record_failed_login(
+    conn: &mut SqliteConnection,
+    username: String,
+    user_id: Option<String>,
+    ip: String,
+    reason: &str,
+) -> Result<FailedLogin, DbError> {
+    use crate::schema::failed_logins;
+
+    let failed_login = FailedLogin {
+        id: Uuid::new_v4().to_string(),
+        username,
+        user_id,
+        ip,
+        reason: reason.to_string(),
+        created_at: iso_date(),
+    };
+
+    diesel::insert_into(failed_logins::table)
+        .values(&failed_login)
+        .execute(conn)?;
+
+    Ok(failed_login)
+}
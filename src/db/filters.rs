@@ -0,0 +1,130 @@
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::{
+    filters::{FilterDecision, FilterRule},
+    models::{FilterDecisionRecord, RoomFilter},
+};
+
+use super::{iso_date, DbError};
+
+/// Number of filter decisions returned per page.
+const DECISION_PAGE_SIZE: i64 = 100;
+
+pub fn list_room_filters(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+) -> Result<Vec<RoomFilter>, DbError> {
+    use crate::schema::room_filters;
+
+    let filters = room_filters::table
+        .filter(room_filters::room_id.eq(room_id))
+        .order(room_filters::created_at)
+        .select(RoomFilter::as_select())
+        .load(conn)?;
+
+    Ok(filters)
+}
+
+pub fn create_room_filter(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    rule: &FilterRule,
+) -> Result<RoomFilter, DbError> {
+    use crate::schema::room_filters;
+
+    let filter = RoomFilter {
+        id: Uuid::new_v4().to_string(),
+        room_id: room_id.to_string(),
+        kind: rule.kind.to_string(),
+        value: rule.value.clone(),
+        action: rule.action.to_string(),
+        created_at: iso_date(),
+    };
+
+    diesel::insert_into(room_filters::table)
+        .values(&filter)
+        .execute(conn)?;
+
+    Ok(filter)
+}
+
+/// Returns `false` if the room has no filter with that id.
+pub fn delete_room_filter(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    filter_id: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::room_filters;
+
+    let deleted = diesel::delete(
+        room_filters::table
+            .filter(room_filters::id.eq(filter_id))
+            .filter(room_filters::room_id.eq(room_id)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted == 1)
+}
+
+/// Record what the filters did to a message. `conversation_id` is `None` for rejected messages.
+pub fn record_decisions(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    user_id: &str,
+    conversation_id: Option<&str>,
+    message: &str,
+    decisions: &[FilterDecision],
+) -> Result<(), DbError> {
+    use crate::schema::filter_decisions;
+
+    if decisions.is_empty() {
+        return Ok(());
+    }
+
+    let created_at = iso_date();
+    let records: Vec<FilterDecisionRecord> = decisions
+        .iter()
+        .map(|decision| FilterDecisionRecord {
+            id: Uuid::new_v4().to_string(),
+            room_id: room_id.to_string(),
+            user_id: user_id.to_string(),
+            conversation_id: conversation_id.map(str::to_string),
+            kind: decision.kind.to_string(),
+            action: decision.action.to_string(),
+            message: message.to_string(),
+            created_at: created_at.clone(),
+        })
+        .collect();
+
+    diesel::insert_into(filter_decisions::table)
+        .values(&records)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Most recent filter decisions in a room, newest first, optionally before a timestamp.
+pub fn list_decisions(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    before: Option<&str>,
+) -> Result<Vec<FilterDecisionRecord>, DbError> {
+    use crate::schema::filter_decisions;
+
+    let mut query = filter_decisions::table
+        .filter(filter_decisions::room_id.eq(room_id))
+        .into_boxed();
+
+    if let Some(before) = before {
+        query = query.filter(filter_decisions::created_at.lt(before));
+    }
+
+    let decisions = query
+        .order(filter_decisions::created_at.desc())
+        .limit(DECISION_PAGE_SIZE)
+        .select(FilterDecisionRecord::as_select())
+        .load(conn)?;
+
+    Ok(decisions)
+}
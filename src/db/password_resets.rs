@@ -0,0 +1,88 @@
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::models::PasswordResetToken;
+
+use super::{iso_date, DbError};
+
+pub fn create_reset_token(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    token_hash: String,
+    ttl_minutes: i64,
+) -> Result<PasswordResetToken, DbError> {
+    use crate::schema::password_reset_tokens;
+
+    let expires_at = (Utc::now() + Duration::minutes(ttl_minutes)).to_rfc3339();
+
+    let token = PasswordResetToken {
+        id: Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        token_hash,
+        expires_at,
+        used_at: None,
+        created_at: iso_date(),
+    };
+
+    diesel::insert_into(password_reset_tokens::table)
+        .values(&token)
+        .execute(conn)?;
+
+    Ok(token)
+}
+
+/// Find an unused, unexpired reset token by its hash.
+pub fn find_valid_reset_token(
+    conn: &mut SqliteConnection,
+    token_hash: &str,
+) -> Result<Option<PasswordResetToken>, DbError> {
+    use crate::schema::password_reset_tokens;
+
+    let token = password_reset_tokens::table
+        .filter(password_reset_tokens::token_hash.eq(token_hash))
+        .filter(password_reset_tokens::used_at.is_null())
+        .first::<PasswordResetToken>(conn)
+        .optional()?;
+
+    let token = token.filter(|token| {
+        DateTime::parse_from_rfc3339(&token.expires_at)
+            .map(|expires_at| expires_at > Utc::now())
+            .unwrap_or(false)
+    });
+
+    Ok(token)
+}
+
+/// Set a new password for the token's user and mark the token as used.
+///
+/// Returns `false` if the token was used in the meantime.
+pub fn reset_password(
+    conn: &mut SqliteConnection,
+    token: &PasswordResetToken,
+    hashed_password: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::{password_reset_tokens, users};
+
+    let reset = conn.transaction(|conn| {
+        let redeemed = diesel::update(
+            password_reset_tokens::table
+                .find(&token.id)
+                .filter(password_reset_tokens::used_at.is_null()),
+        )
+        .set(password_reset_tokens::used_at.eq(iso_date()))
+        .execute(conn)?;
+
+        if redeemed == 0 {
+            return Ok(false);
+        }
+
+        diesel::update(users::table.find(&token.user_id))
+            .set(users::password.eq(hashed_password))
+            .execute(conn)?;
+
+        diesel::result::QueryResult::Ok(true)
+    })?;
+
+    Ok(reset)
+}
@@ -0,0 +1,186 @@
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::models::{Conversation, Report, ReportResponse, User};
+
+use super::{iso_date, DbError};
+
+/// Messages shown on either side of a reported message.
+const CONTEXT_MESSAGES: i64 = 5;
+
+pub fn create_report(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    reporter_id: &str,
+    reported_user_id: &str,
+    conversation_id: Option<&str>,
+    reason: &str,
+) -> Result<Report, DbError> {
+    use crate::schema::reports;
+
+    let report = Report {
+        id: Uuid::new_v4().to_string(),
+        room_id: room_id.to_string(),
+        reporter_id: reporter_id.to_string(),
+        reported_user_id: reported_user_id.to_string(),
+        conversation_id: conversation_id.map(str::to_string),
+        reason: reason.to_string(),
+        status: Report::OPEN.to_string(),
+        resolution: None,
+        resolved_by: None,
+        resolved_at: None,
+        created_at: iso_date(),
+    };
+
+    diesel::insert_into(reports::table)
+        .values(&report)
+        .execute(conn)?;
+
+    Ok(report)
+}
+
+pub fn find_report(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    report_id: &str,
+) -> Result<Option<Report>, DbError> {
+    use crate::schema::reports;
+
+    let report = reports::table
+        .find(report_id)
+        .filter(reports::room_id.eq(room_id))
+        .select(Report::as_select())
+        .first(conn)
+        .optional()?;
+
+    Ok(report)
+}
+
+/// Open reports in a room, oldest first, with the reported message and its surroundings.
+pub fn list_open_reports(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+) -> Result<Vec<ReportResponse>, DbError> {
+    use crate::schema::{conversations, reports, users};
+
+    let open: Vec<Report> = reports::table
+        .filter(reports::room_id.eq(room_id))
+        .filter(reports::status.eq(Report::OPEN))
+        .order(reports::created_at)
+        .select(Report::as_select())
+        .load(conn)?;
+
+    open.into_iter()
+        .map(|report| {
+            let reporter = users::table
+                .find(&report.reporter_id)
+                .select(User::as_select())
+                .first(conn)
+                .optional()?;
+            let reported_user = users::table
+                .find(&report.reported_user_id)
+                .select(User::as_select())
+                .first(conn)
+                .optional()?;
+
+            let message = match &report.conversation_id {
+                Some(id) => conversations::table
+                    .find(id)
+                    .select(Conversation::as_select())
+                    .first(conn)
+                    .optional()?,
+                None => None,
+            };
+
+            let context = match &message {
+                Some(message) => message_context(conn, message)?,
+                None => Vec::new(),
+            };
+
+            Ok(ReportResponse {
+                report,
+                reporter,
+                reported_user,
+                message,
+                context,
+            })
+        })
+        .collect()
+}
+
+fn message_context(
+    conn: &mut SqliteConnection,
+    message: &Conversation,
+) -> Result<Vec<Conversation>, DbError> {
+    use crate::schema::conversations;
+
+    let mut before: Vec<Conversation> = conversations::table
+        .filter(conversations::room_id.eq(&message.room_id))
+        .filter(conversations::created_at.lt(&message.created_at))
+        .order(conversations::created_at.desc())
+        .limit(CONTEXT_MESSAGES)
+        .select(Conversation::as_select())
+        .load(conn)?;
+    before.reverse();
+
+    let after: Vec<Conversation> = conversations::table
+        .filter(conversations::room_id.eq(&message.room_id))
+        .filter(conversations::created_at.gt(&message.created_at))
+        .order(conversations::created_at)
+        .limit(CONTEXT_MESSAGES)
+        .select(Conversation::as_select())
+        .load(conn)?;
+
+    Ok(before
+        .into_iter()
+        .chain(std::iter::once(message.clone()))
+        .chain(after)
+        .collect())
+}
+
+/// Mark the report as resolved. When a message was deleted, other open reports about the
+/// same message are resolved with it.
+///
+/// Returns `false` if the report was no longer open, e.g. because another moderator resolved
+/// it first.
+pub fn resolve_report(
+    conn: &mut SqliteConnection,
+    report: &Report,
+    resolution: &str,
+    resolved_by: &str,
+    include_same_message: bool,
+) -> Result<bool, DbError> {
+    use crate::schema::reports;
+
+    let now = iso_date();
+    let changes = (
+        reports::status.eq(Report::RESOLVED),
+        reports::resolution.eq(resolution),
+        reports::resolved_by.eq(resolved_by),
+        reports::resolved_at.eq(&now),
+    );
+
+    let resolved = diesel::update(
+        reports::table
+            .find(&report.id)
+            .filter(reports::status.eq(Report::OPEN)),
+    )
+    .set(changes)
+    .execute(conn)?;
+
+    if resolved == 0 {
+        return Ok(false);
+    }
+
+    if let (Some(conversation_id), true) = (&report.conversation_id, include_same_message) {
+        diesel::update(
+            reports::table
+                .filter(reports::conversation_id.eq(conversation_id))
+                .filter(reports::status.eq(Report::OPEN)),
+        )
+        .set(changes)
+        .execute(conn)?;
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use diesel::{dsl, prelude::*};
+use serde_json::Value;
+
+use crate::models::RoomEvent;
+
+use super::{iso_date, DbError};
+
+/// Append an event to a room's log under the room's next sequence number, which `frame` turns
+/// into the payload. `conversation_id` is the message the event carries, if any. Only the last
+/// `retention` events of the room are kept.
+///
+/// Returns the sequence number and the payload.
+pub fn append(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    sender_id: Option<&str>,
+    conversation_id: Option<&str>,
+    frame: impl FnOnce(i64) -> String,
+    retention: i64,
+) -> Result<(i64, String), DbError> {
+    use crate::schema::room_events;
+
+    // take the write lock up front, so two writers can't pick the same number
+    let event = conn.immediate_transaction(|conn| {
+        let last: Option<i64> = room_events::table
+            .filter(room_events::room_id.eq(room_id))
+            .select(dsl::max(room_events::seq))
+            .first(conn)?;
+        let seq = last.unwrap_or(0) + 1;
+
+        let event = RoomEvent {
+            room_id: room_id.to_string(),
+            seq,
+            sender_id: sender_id.map(str::to_string),
+            payload: frame(seq),
+            created_at: iso_date(),
+            conversation_id: conversation_id.map(str::to_string),
+        };
+
+        diesel::insert_into(room_events::table)
+            .values(&event)
+            .execute(conn)?;
+
+        diesel::delete(
+            room_events::table
+                .filter(room_events::room_id.eq(room_id))
+                .filter(room_events::seq.le(seq - retention.max(1))),
+        )
+        .execute(conn)?;
+
+        diesel::result::QueryResult::Ok(event)
+    })?;
+
+    Ok((event.seq, event.payload))
+}
+
+/// The sequence number of the latest event in each of the rooms that has any.
+pub fn last_seqs(
+    conn: &mut SqliteConnection,
+    room_ids: &[String],
+) -> Result<HashMap<String, i64>, DbError> {
+    use crate::schema::room_events;
+
+    let seqs: Vec<(String, Option<i64>)> = room_events::table
+        .filter(room_events::room_id.eq_any(room_ids))
+        .group_by(room_events::room_id)
+        .select((room_events::room_id, dsl::max(room_events::seq)))
+        .load(conn)?;
+
+    Ok(seqs
+        .into_iter()
+        .filter_map(|(room_id, seq)| Some((room_id, seq?)))
+        .collect())
+}
+
+/// Up to `limit` events of a room that came after `after`, oldest first.
+pub fn since(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    after: i64,
+    limit: i64,
+) -> Result<Vec<RoomEvent>, DbError> {
+    use crate::schema::room_events;
+
+    let events = room_events::table
+        .filter(room_events::room_id.eq(room_id))
+        .filter(room_events::seq.gt(after))
+        .order(room_events::seq.asc())
+        .limit(limit)
+        .select(RoomEvent::as_select())
+        .load(conn)?;
+
+    Ok(events)
+}
+
+/// Forget the events carrying a message, after the message was deleted.
+pub fn delete_for_conversation(
+    conn: &mut SqliteConnection,
+    conversation_id: &str,
+) -> Result<(), DbError> {
+    use crate::schema::room_events;
+
+    diesel::delete(room_events::table.filter(room_events::conversation_id.eq(conversation_id)))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Forget the events carrying a user's messages.
+pub fn delete_for_sender(conn: &mut SqliteConnection, sender_id: &str) -> Result<(), DbError> {
+    use crate::schema::room_events;
+
+    diesel::delete(room_events::table.filter(room_events::sender_id.eq(sender_id)))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Attribute the events carrying a user's messages to `new_sender`, in the payload too, like
+/// the messages themselves.
+pub fn reassign_sender(
+    conn: &mut SqliteConnection,
+    sender_id: &str,
+    new_sender: &str,
+) -> Result<(), DbError> {
+    use crate::schema::room_events;
+
+    let events = room_events::table
+        .filter(room_events::sender_id.eq(sender_id))
+        .select(RoomEvent::as_select())
+        .load(conn)?;
+
+    for event in events {
+        let mut frame: Value = serde_json::from_str(&event.payload)?;
+        if let Some(data) = frame.get_mut("data").and_then(Value::as_object_mut) {
+            data.insert("user_id".to_string(), Value::from(new_sender));
+        }
+
+        diesel::update(room_events::table.find((&event.room_id, event.seq)))
+            .set((
+                room_events::sender_id.eq(new_sender),
+                room_events::payload.eq(frame.to_string()),
+            ))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
@@ -1,18 +1,9 @@
-use crate::models::*;
-use diesel::prelude::*;
-
 use crate::{
-    models::{Conversation, ListRoomResponse, NewConversation, Room, RoomResponse, RoomUser, User},
-    schema::{conversations, rooms, rooms_users, users},
+    models::{Conversation, ListRoomResponse, Room, RoomResponse, RoomUser, User},
+    schema::{rooms, rooms_users, users},
 };
-use actix_web::web;
-use bcrypt::{hash, DEFAULT_COST};
-use chrono::{DateTime, Utc};
 use diesel::prelude::*;
-use std::{
-    collections::{HashMap, HashSet},
-    time::SystemTime,
-};
+use std::collections::HashSet;
 
 use uuid::Uuid;
 // use crate::schema::rooms_users::dsl::rooms_users;
@@ -26,23 +17,24 @@ pub fn get_room(
         .filter(rooms::id.eq(room_id.to_string()))
         .first::<Room>(conn);
 
-    if room.is_err() {
-        let err = room.unwrap_err();
-        match err {
-            diesel::result::Error::NotFound => {
-                return Ok(None);
-            }
-            _ => return Err(err.into()),
-        }
-    }
-
-    let room = room.unwrap();
+    let room = match room {
+        Ok(room) => room,
+        Err(diesel::result::Error::NotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
 
-    let users = RoomUser::belonging_to(&room)
+    let members: Vec<(RoomUser, User)> = RoomUser::belonging_to(&room)
         .inner_join(users::table)
-        .select(User::as_select())
+        .select((RoomUser::as_select(), User::as_select()))
         .load(conn)?;
 
+    let moderator_ids = members
+        .iter()
+        .filter(|(member, _)| member.role == RoomUser::MODERATOR)
+        .map(|(member, _)| member.user_id.clone())
+        .collect();
+    let users: Vec<User> = members.into_iter().map(|(_, user)| user).collect();
+
     let conversations = Conversation::belonging_to(&room)
         .select(Conversation::as_select())
         .load(conn)?;
@@ -71,13 +63,24 @@ pub fn get_room(
     Ok(Some(RoomResponse {
         room,
         users,
+        moderator_ids,
         conversations,
         exited_users,
     }))
 }
 
-pub fn get_all_rooms(conn: &mut SqliteConnection) -> Result<Vec<ListRoomResponse>, DbError> {
-    let all_rooms = rooms::table.select(Room::as_select()).load(conn)?;
+/// Rooms that aren't archived, or only the archived ones when `archived` is set.
+pub fn get_all_rooms(
+    conn: &mut SqliteConnection,
+    archived: bool,
+) -> Result<Vec<ListRoomResponse>, DbError> {
+    let mut query = rooms::table.select(Room::as_select()).into_boxed();
+    query = if archived {
+        query.filter(rooms::archived_at.is_not_null())
+    } else {
+        query.filter(rooms::archived_at.is_null())
+    };
+    let all_rooms = query.load(conn)?;
 
     let users: Vec<(RoomUser, User)> = RoomUser::belonging_to(&all_rooms)
         .inner_join(users::table)
@@ -88,11 +91,9 @@ pub fn get_all_rooms(conn: &mut SqliteConnection) -> Result<Vec<ListRoomResponse
         .grouped_by(&all_rooms)
         .into_iter()
         .zip(all_rooms)
-        .map(|(users, room)| {
-            return ListRoomResponse {
-                room,
-                users: users.into_iter().map(|(_, user)| user).collect(),
-            };
+        .map(|(users, room)| ListRoomResponse {
+            room,
+            users: users.into_iter().map(|(_, user)| user).collect(),
         })
         .collect();
 
@@ -112,6 +113,8 @@ pub fn create_room(
         last_message: "".to_string(),
         owner_id: creator_id.to_string(),
         created_at: iso_date(),
+        slow_mode_seconds: 0,
+        archived_at: None,
     };
 
     diesel::insert_into(rooms).values(&new_room).execute(conn)?;
@@ -121,6 +124,11 @@ pub fn create_room(
 
 pub fn delete_room(conn: &mut SqliteConnection, room_id: Uuid) -> Result<(), DbError> {
     use crate::schema::conversations;
+    use crate::schema::filter_decisions;
+    use crate::schema::reports;
+    use crate::schema::room_bans;
+    use crate::schema::room_events;
+    use crate::schema::room_filters;
     use crate::schema::rooms;
     use crate::schema::rooms_users;
 
@@ -138,8 +146,23 @@ pub fn delete_room(conn: &mut SqliteConnection, room_id: Uuid) -> Result<(), DbE
         diesel::delete(rooms_users::table.filter(rooms_users::room_id.eq(&room_id)))
             .execute(connection)?;
 
+        // delete content filters and their decisions
+        diesel::delete(room_filters::table.filter(room_filters::room_id.eq(&room_id)))
+            .execute(connection)?;
+        diesel::delete(filter_decisions::table.filter(filter_decisions::room_id.eq(&room_id)))
+            .execute(connection)?;
+
+        // delete reports and bans
+        diesel::delete(reports::table.filter(reports::room_id.eq(&room_id))).execute(connection)?;
+        diesel::delete(room_bans::table.filter(room_bans::room_id.eq(&room_id)))
+            .execute(connection)?;
+
+        // delete the event log
+        diesel::delete(room_events::table.filter(room_events::room_id.eq(&room_id)))
+            .execute(connection)?;
+
         diesel::result::QueryResult::Ok(())
-    });
+    })?;
 
     Ok(())
 }
@@ -158,3 +181,40 @@ pub fn get_user_joined_rooms(
 
     Ok(rooms)
 }
+
+pub fn find_room(conn: &mut SqliteConnection, room_id: &str) -> Result<Option<Room>, DbError> {
+    let room = rooms::table
+        .find(room_id)
+        .select(Room::as_select())
+        .first(conn)
+        .optional()?;
+
+    Ok(room)
+}
+
+/// Archive or unarchive a room.
+pub fn set_archived(
+    conn: &mut SqliteConnection,
+    room_id: &str,
+    archived: bool,
+) -> Result<Room, DbError> {
+    let room = diesel::update(rooms::table.find(room_id))
+        .set(rooms::archived_at.eq(archived.then(iso_date)))
+        .returning(Room::as_returning())
+        .get_result(conn)?;
+
+    Ok(room)
+}
+
+pub fn set_slow_mode(
+    conn: &mut SqliteConnection,
+    room_id: Uuid,
+    seconds: i32,
+) -> Result<Room, DbError> {
+    let room = diesel::update(rooms::table.find(room_id.to_string()))
+        .set(rooms::slow_mode_seconds.eq(seconds))
+        .returning(Room::as_returning())
+        .get_result(conn)?;
+
+    Ok(room)
+}
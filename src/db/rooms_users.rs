@@ -1,28 +1,37 @@
 use diesel::prelude::*;
-use serde_json::json;
 use uuid::Uuid;
 
-use crate::models::{ListRoomResponse, RoomUser};
+use crate::models::{Room, RoomBan, RoomUser};
 
-use super::DbError;
+use super::{iso_date, DbError};
 
-pub fn join_room(conn: &mut SqliteConnection, user_id: Uuid, room_id: Uuid) -> Result<(), DbError> {
+/// Returns `false` if the user already was a member.
+pub fn join_room(
+    conn: &mut SqliteConnection,
+    user_id: Uuid,
+    room_id: Uuid,
+) -> Result<bool, DbError> {
     use crate::schema::rooms_users;
 
-    diesel::insert_into(rooms_users::table)
+    let inserted = diesel::insert_or_ignore_into(rooms_users::table)
         .values((
             rooms_users::room_id.eq(room_id.to_string()),
             rooms_users::user_id.eq(user_id.to_string()),
         ))
         .execute(conn)?;
 
-    Ok(())
+    Ok(inserted == 1)
 }
 
-pub fn exit_room(conn: &mut SqliteConnection, user_id: Uuid, room_id: Uuid) -> Result<(), DbError> {
+/// Returns `false` if the user wasn't a member.
+pub fn exit_room(
+    conn: &mut SqliteConnection,
+    user_id: Uuid,
+    room_id: Uuid,
+) -> Result<bool, DbError> {
     use crate::schema::rooms_users;
 
-    diesel::delete(
+    let deleted = diesel::delete(
         rooms_users::table.filter(
             rooms_users::room_id
                 .eq(room_id.to_string())
@@ -31,5 +40,104 @@ pub fn exit_room(conn: &mut SqliteConnection, user_id: Uuid, room_id: Uuid) -> R
     )
     .execute(conn)?;
 
+    Ok(deleted == 1)
+}
+
+/// Role of a user in a room, or `None` if they aren't a member.
+pub fn find_role(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    room_id: &str,
+) -> Result<Option<String>, DbError> {
+    use crate::schema::rooms_users;
+
+    let role = rooms_users::table
+        .find((room_id, user_id))
+        .select(rooms_users::role)
+        .first(conn)
+        .optional()?;
+
+    Ok(role)
+}
+
+/// Whether the user owns the room or is one of its moderators.
+pub fn is_moderator(
+    conn: &mut SqliteConnection,
+    room: &Room,
+    user_id: &str,
+) -> Result<bool, DbError> {
+    if room.owner_id == user_id {
+        return Ok(true);
+    }
+
+    let role = find_role(conn, user_id, &room.id)?;
+    Ok(role.as_deref() == Some(RoomUser::MODERATOR))
+}
+
+/// Change the role of a member. Returns `false` if the user isn't a member of the room.
+pub fn set_role(
+    conn: &mut SqliteConnection,
+    user_id: Uuid,
+    room_id: Uuid,
+    role: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::rooms_users;
+
+    let updated =
+        diesel::update(rooms_users::table.find((room_id.to_string(), user_id.to_string())))
+            .set(rooms_users::role.eq(role))
+            .execute(conn)?;
+
+    Ok(updated == 1)
+}
+
+pub fn is_banned(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    room_id: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::room_bans;
+
+    let banned = diesel::select(diesel::dsl::exists(
+        room_bans::table.find((room_id, user_id)),
+    ))
+    .get_result(conn)?;
+
+    Ok(banned)
+}
+
+/// Remove the user from the room and keep them from joining again.
+pub fn ban(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    room_id: &str,
+    banned_by: &str,
+) -> Result<(), DbError> {
+    use crate::schema::{room_bans, rooms_users};
+
+    conn.transaction(|conn| {
+        diesel::delete(rooms_users::table.find((room_id, user_id))).execute(conn)?;
+
+        diesel::insert_or_ignore_into(room_bans::table)
+            .values(&RoomBan {
+                room_id: room_id.to_string(),
+                user_id: user_id.to_string(),
+                banned_by: banned_by.to_string(),
+                created_at: iso_date(),
+            })
+            .execute(conn)?;
+
+        diesel::result::QueryResult::Ok(())
+    })?;
+
     Ok(())
 }
+
+/// Returns `false` if the user wasn't banned.
+pub fn unban(conn: &mut SqliteConnection, user_id: &str, room_id: &str) -> Result<bool, DbError> {
+    use crate::schema::room_bans;
+
+    let deleted = diesel::delete(room_bans::table.find((room_id, user_id))).execute(conn)?;
+
+    Ok(deleted == 1)
+}
@@ -0,0 +1,41 @@
+use diesel::prelude::*;
+use serde::Serialize;
+
+use crate::models::Report;
+
+use super::{users::DELETED_USER_ID, DbError};
+
+/// Row counts shown on the admin dashboard.
+#[derive(Debug, Clone, Serialize)]
+pub struct SiteCounts {
+    pub users: i64,
+    pub suspended_users: i64,
+    pub admins: i64,
+    pub rooms: i64,
+    pub messages: i64,
+    pub open_reports: i64,
+}
+
+pub fn site_counts(conn: &mut SqliteConnection) -> Result<SiteCounts, DbError> {
+    use crate::schema::{conversations, reports, rooms, users};
+
+    let real_users = users::table.filter(users::id.ne(DELETED_USER_ID));
+
+    Ok(SiteCounts {
+        users: real_users.count().get_result(conn)?,
+        suspended_users: real_users
+            .filter(users::suspended_at.is_not_null())
+            .count()
+            .get_result(conn)?,
+        admins: real_users
+            .filter(users::is_admin)
+            .count()
+            .get_result(conn)?,
+        rooms: rooms::table.count().get_result(conn)?,
+        messages: conversations::table.count().get_result(conn)?,
+        open_reports: reports::table
+            .filter(reports::status.eq(Report::OPEN))
+            .count()
+            .get_result(conn)?,
+    })
+}
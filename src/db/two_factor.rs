@@ -0,0 +1,135 @@
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::models::RecoveryCode;
+
+use super::{iso_date, DbError};
+
+/// Store a freshly generated TOTP secret. Two-factor stays disabled until it is confirmed.
+pub fn set_pending_secret(
+    conn: &mut SqliteConnection,
+    user_id: Uuid,
+    secret: &str,
+) -> Result<(), DbError> {
+    use crate::schema::users;
+
+    diesel::update(users::table.find(user_id.to_string()))
+        .set((
+            users::totp_secret.eq(secret),
+            users::totp_enabled.eq(false),
+            users::totp_last_step.eq(None::<i64>),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Enable two-factor and replace the user's recovery codes with the given hashes.
+pub fn enable(
+    conn: &mut SqliteConnection,
+    user_id: Uuid,
+    last_step: i64,
+    code_hashes: Vec<String>,
+) -> Result<(), DbError> {
+    use crate::schema::{recovery_codes, users};
+
+    let user_id = user_id.to_string();
+
+    conn.transaction(|conn| {
+        diesel::update(users::table.find(&user_id))
+            .set((
+                users::totp_enabled.eq(true),
+                users::totp_last_step.eq(last_step),
+            ))
+            .execute(conn)?;
+
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(&user_id)))
+            .execute(conn)?;
+
+        let created_at = iso_date();
+        let codes: Vec<RecoveryCode> = code_hashes
+            .into_iter()
+            .map(|code_hash| RecoveryCode {
+                id: Uuid::new_v4().to_string(),
+                user_id: user_id.clone(),
+                code_hash,
+                used_at: None,
+                created_at: created_at.clone(),
+            })
+            .collect();
+
+        diesel::insert_into(recovery_codes::table)
+            .values(&codes)
+            .execute(conn)?;
+
+        diesel::result::QueryResult::Ok(())
+    })?;
+
+    Ok(())
+}
+
+pub fn disable(conn: &mut SqliteConnection, user_id: Uuid) -> Result<(), DbError> {
+    use crate::schema::{recovery_codes, users};
+
+    let user_id = user_id.to_string();
+
+    conn.transaction(|conn| {
+        diesel::update(users::table.find(&user_id))
+            .set((
+                users::totp_secret.eq(None::<String>),
+                users::totp_enabled.eq(false),
+                users::totp_last_step.eq(None::<i64>),
+            ))
+            .execute(conn)?;
+
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(&user_id)))
+            .execute(conn)?;
+
+        diesel::result::QueryResult::Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Remember the last accepted time step so the same code can't be replayed.
+///
+/// Returns `false` if a code of this step or a later one was accepted in the meantime.
+pub fn set_last_step(
+    conn: &mut SqliteConnection,
+    user_id: Uuid,
+    last_step: i64,
+) -> Result<bool, DbError> {
+    use crate::schema::users;
+
+    let updated = diesel::update(
+        users::table.find(user_id.to_string()).filter(
+            users::totp_last_step
+                .is_null()
+                .or(users::totp_last_step.lt(last_step)),
+        ),
+    )
+    .set(users::totp_last_step.eq(last_step))
+    .execute(conn)?;
+
+    Ok(updated == 1)
+}
+
+/// Mark a recovery code as used. Returns `false` if no unused code matches.
+pub fn use_recovery_code(
+    conn: &mut SqliteConnection,
+    user_id: Uuid,
+    code_hash: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::recovery_codes;
+
+    let updated = diesel::update(
+        recovery_codes::table
+            .filter(recovery_codes::user_id.eq(user_id.to_string()))
+            .filter(recovery_codes::code_hash.eq(code_hash))
+            .filter(recovery_codes::used_at.is_null()),
+    )
+    .set(recovery_codes::used_at.eq(iso_date()))
+    .execute(conn)?;
+
+    Ok(updated == 1)
+}
@@ -1,10 +1,18 @@
-use crate::{db::iso_date, models::User};
-use bcrypt::hash;
+use crate::{
+    db::iso_date,
+    models::{DeletedAccount, MessagePolicy, ProfileChanges, Room, RoomPolicy, User},
+    password::hash_password,
+};
 use diesel::prelude::*;
 use uuid::Uuid;
 
 use super::DbError;
 
+/// Placeholder account that messages of deleted accounts are attributed to.
+///
+/// It can't be looked up by username, so nobody can sign in as it or reset its password.
+pub const DELETED_USER_ID: &str = "00000000-0000-0000-0000-000000000000";
+
 pub fn find_user_by_uid(conn: &mut SqliteConnection, uid: Uuid) -> Result<Option<User>, DbError> {
     use crate::schema::users::dsl::*;
 
@@ -22,24 +30,322 @@ pub fn find_user_by_username(
     use crate::schema::users::dsl::*;
     let user = users
         .filter(username.eq(un))
+        .filter(id.ne(DELETED_USER_ID))
         .first::<User>(conn)
         .optional()?;
 
     Ok(user)
 }
 
-pub fn insert_new_user(conn: &mut SqliteConnection, un: &str, pw: &str) -> Result<User, DbError> {
+pub fn insert_new_user(
+    conn: &mut SqliteConnection,
+    un: &str,
+    pw: &str,
+    cost: u32,
+) -> Result<User, DbError> {
     use crate::schema::users::dsl::*;
 
-    let hashed_password = hash(pw, 4).unwrap();
+    let hashed_password = hash_password(pw, cost)?;
 
     let new_user = User {
         id: Uuid::new_v4().to_string(),
         username: un.to_owned(),
         password: hashed_password,
         created_at: iso_date(),
+        totp_secret: None,
+        totp_enabled: false,
+        totp_last_step: None,
+        display_name: None,
+        avatar_url: None,
+        bio: None,
+        status_message: None,
+        is_admin: false,
+        suspended_at: None,
+        suspended_reason: None,
     };
     diesel::insert_into(users).values(&new_user).execute(conn)?;
 
     Ok(new_user)
 }
+
+pub fn update_password(
+    conn: &mut SqliteConnection,
+    uid: Uuid,
+    hashed_password: &str,
+) -> Result<(), DbError> {
+    use crate::schema::users::dsl::*;
+
+    diesel::update(users.filter(id.eq(uid.to_string())))
+        .set(password.eq(hashed_password))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn update_profile(
+    conn: &mut SqliteConnection,
+    uid: Uuid,
+    changes: &ProfileChanges,
+) -> Result<User, DbError> {
+    use crate::schema::users::dsl::*;
+
+    let user = diesel::update(users.filter(id.eq(uid.to_string())))
+        .set(changes)
+        .returning(User::as_returning())
+        .get_result(conn)?;
+
+    Ok(user)
+}
+
+/// Delete an account and everything that only makes sense with it, in one transaction.
+pub fn delete_account(
+    conn: &mut SqliteConnection,
+    uid: Uuid,
+    room_policy: RoomPolicy,
+    message_policy: MessagePolicy,
+) -> Result<DeletedAccount, DbError> {
+    use crate::schema::{
+        conversations, failed_logins, filter_decisions, password_reset_tokens, recovery_codes,
+        reports, room_bans, rooms, rooms_users, user_blocks, users,
+    };
+
+    let uid = uid.to_string();
+
+    conn.transaction(|conn| {
+        let mut deleted = DeletedAccount::default();
+
+        let owned_rooms = rooms::table
+            .filter(rooms::owner_id.eq(&uid))
+            .select(Room::as_select())
+            .load(conn)?;
+
+        for mut room in owned_rooms {
+            let new_owner = match room_policy {
+                RoomPolicy::Transfer => rooms_users::table
+                    .filter(rooms_users::room_id.eq(&room.id))
+                    .filter(rooms_users::user_id.ne(&uid))
+                    .select(rooms_users::user_id)
+                    .order(rooms_users::user_id)
+                    .first::<String>(conn)
+                    .optional()?,
+                RoomPolicy::Delete => None,
+            };
+
+            match new_owner {
+                Some(new_owner) => {
+                    diesel::update(rooms::table.find(&room.id))
+                        .set(rooms::owner_id.eq(&new_owner))
+                        .execute(conn)?;
+                    room.owner_id = new_owner;
+                    deleted.transferred_rooms.push(room);
+                }
+                None => {
+                    // the placeholder keeps the room until an admin deletes it
+                    let newly_archived = !room.is_archived();
+                    let archived_at = room.archived_at.clone().unwrap_or_else(iso_date);
+                    let room = diesel::update(rooms::table.find(&room.id))
+                        .set((
+                            rooms::owner_id.eq(DELETED_USER_ID),
+                            rooms::archived_at.eq(archived_at),
+                        ))
+                        .returning(Room::as_returning())
+                        .get_result(conn)?;
+                    if newly_archived {
+                        deleted.archived_rooms.push(room);
+                    }
+                }
+            }
+        }
+
+        deleted.left_rooms = rooms_users::table
+            .filter(rooms_users::user_id.eq(&uid))
+            .select(rooms_users::room_id)
+            .load(conn)?;
+
+        diesel::delete(rooms_users::table.filter(rooms_users::user_id.eq(&uid))).execute(conn)?;
+
+        match message_policy {
+            MessagePolicy::Anonymize => {
+                diesel::update(conversations::table.filter(conversations::user_id.eq(&uid)))
+                    .set(conversations::user_id.eq(DELETED_USER_ID))
+                    .execute(conn)?;
+                diesel::update(filter_decisions::table.filter(filter_decisions::user_id.eq(&uid)))
+                    .set(filter_decisions::user_id.eq(DELETED_USER_ID))
+                    .execute(conn)?;
+                super::room_events::reassign_sender(conn, &uid, DELETED_USER_ID)?;
+            }
+            MessagePolicy::Delete => {
+                // reports about the messages stay, as reports about the account
+                diesel::update(
+                    reports::table.filter(
+                        reports::conversation_id.eq_any(
+                            conversations::table
+                                .filter(conversations::user_id.eq(&uid))
+                                .select(conversations::id.nullable()),
+                        ),
+                    ),
+                )
+                .set(reports::conversation_id.eq(None::<String>))
+                .execute(conn)?;
+                diesel::delete(conversations::table.filter(conversations::user_id.eq(&uid)))
+                    .execute(conn)?;
+                diesel::delete(filter_decisions::table.filter(filter_decisions::user_id.eq(&uid)))
+                    .execute(conn)?;
+                super::room_events::delete_for_sender(conn, &uid)?;
+            }
+        }
+
+        diesel::delete(
+            password_reset_tokens::table.filter(password_reset_tokens::user_id.eq(&uid)),
+        )
+        .execute(conn)?;
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(&uid)))
+            .execute(conn)?;
+
+        // reports filed by the account go, reports about it stay with the moderators
+        diesel::delete(reports::table.filter(reports::reporter_id.eq(&uid))).execute(conn)?;
+        diesel::update(reports::table.filter(reports::reported_user_id.eq(&uid)))
+            .set(reports::reported_user_id.eq(DELETED_USER_ID))
+            .execute(conn)?;
+        diesel::update(reports::table.filter(reports::resolved_by.eq(&uid)))
+            .set(reports::resolved_by.eq(DELETED_USER_ID))
+            .execute(conn)?;
+
+        diesel::delete(room_bans::table.filter(room_bans::user_id.eq(&uid))).execute(conn)?;
+        diesel::update(room_bans::table.filter(room_bans::banned_by.eq(&uid)))
+            .set(room_bans::banned_by.eq(DELETED_USER_ID))
+            .execute(conn)?;
+
+        diesel::delete(
+            user_blocks::table.filter(
+                user_blocks::blocker_id
+                    .eq(&uid)
+                    .or(user_blocks::blocked_id.eq(&uid)),
+            ),
+        )
+        .execute(conn)?;
+
+        // keep failed sign in records for auditing, but unlinked from the account
+        diesel::update(failed_logins::table.filter(failed_logins::user_id.eq(&uid)))
+            .set(failed_logins::user_id.eq(None::<String>))
+            .execute(conn)?;
+
+        diesel::delete(users::table.find(&uid)).execute(conn)?;
+
+        let room_ids =
+            |rooms: &[Room]| rooms.iter().map(|room| room.id.clone()).collect::<Vec<_>>();
+        super::audit::record(
+            conn,
+            Some(&uid),
+            "user.delete",
+            None,
+            Some(&uid),
+            serde_json::json!({
+                "room_policy": room_policy,
+                "message_policy": message_policy,
+                "archived_rooms": room_ids(&deleted.archived_rooms),
+                "transferred_rooms": room_ids(&deleted.transferred_rooms),
+            }),
+        )?;
+
+        Ok(deleted)
+    })
+}
+
+/// Users ordered by username, optionally only those whose username contains `search`.
+pub fn list_users(
+    conn: &mut SqliteConnection,
+    search: Option<&str>,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<User>, DbError> {
+    use crate::schema::users;
+
+    let mut query = users::table
+        .filter(users::id.ne(DELETED_USER_ID))
+        .into_boxed();
+
+    if let Some(search) = search {
+        let escaped = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        query = query.filter(users::username.like(format!("%{escaped}%")).escape('\\'));
+    }
+
+    let users = query
+        .order(users::username)
+        .offset(offset)
+        .limit(limit)
+        .select(User::as_select())
+        .load(conn)?;
+
+    Ok(users)
+}
+
+/// Suspend the user with `reason`, or lift the suspension when `reason` is `None`.
+///
+/// Returns the updated user, or `None` if there is no such user.
+pub fn set_suspended(
+    conn: &mut SqliteConnection,
+    uid: Uuid,
+    reason: Option<&str>,
+) -> Result<Option<User>, DbError> {
+    use crate::schema::users;
+
+    let suspended_at = reason.map(|_| iso_date());
+
+    let user = diesel::update(users::table.find(uid.to_string()))
+        .set((
+            users::suspended_at.eq(suspended_at),
+            users::suspended_reason.eq(reason),
+        ))
+        .returning(User::as_returning())
+        .get_result(conn)
+        .optional()?;
+
+    Ok(user)
+}
+
+/// Grant or revoke the site admin role. Returns `None` if there is no such user.
+pub fn set_admin(
+    conn: &mut SqliteConnection,
+    uid: Uuid,
+    is_admin: bool,
+) -> Result<Option<User>, DbError> {
+    use crate::schema::users;
+
+    let user = diesel::update(users::table.find(uid.to_string()))
+        .set(users::is_admin.eq(is_admin))
+        .returning(User::as_returning())
+        .get_result(conn)
+        .optional()?;
+
+    Ok(user)
+}
+
+/// Make `username` a site admin, but only while the site has no admin at all.
+///
+/// Returns whether the user was promoted.
+pub fn bootstrap_admin(conn: &mut SqliteConnection, username: &str) -> Result<bool, DbError> {
+    use crate::schema::users;
+
+    conn.transaction(|conn| {
+        let has_admin: bool =
+            diesel::select(diesel::dsl::exists(users::table.filter(users::is_admin)))
+                .get_result(conn)?;
+        if has_admin {
+            return Ok(false);
+        }
+
+        let updated = diesel::update(
+            users::table
+                .filter(users::username.eq(username))
+                .filter(users::id.ne(DELETED_USER_ID)),
+        )
+        .set(users::is_admin.eq(true))
+        .execute(conn)?;
+
+        Ok(updated == 1)
+    })
+}
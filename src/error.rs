@@ -0,0 +1,209 @@
+//! The error type of the HTTP API.
+//!
+//! Every error is answered with the same JSON shape, a stable machine-readable `code` and a
+//! human-readable `message`, plus extra fields for some errors:
+//!
+//! ```json
+//! {"code": "rate_limited", "message": "Too many requests. Try again in 3 seconds.", "retry_after": 3}
+//! ```
+
+use std::fmt;
+
+use actix_session::{SessionGetError, SessionInsertError};
+use actix_web::{
+    error::BlockingError,
+    http::{header, StatusCode},
+    HttpResponse, HttpResponseBuilder, ResponseError,
+};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde_json::{json, Map, Value};
+
+use crate::db::DbError;
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is malformed or a value is out of range.
+    BadRequest(String),
+    /// Not signed in, or wrong credentials.
+    Unauthorized(String),
+    /// Signed in, but not allowed to do this.
+    Forbidden(String),
+    NotFound(String),
+    /// The request conflicts with the current state, like joining a room twice.
+    Conflict(String),
+    /// The request is well formed but refused, like a message rejected by a filter.
+    Unprocessable(String),
+    /// Too many requests. `retry_after` is in seconds.
+    RateLimited {
+        message: String,
+        retry_after: u64,
+    },
+    /// The server can't take the request right now, e.g. while it shuts down.
+    Unavailable(String),
+    Database(DieselError),
+    /// Anything else. The message is logged, never sent to the client.
+    Internal(String),
+    /// Another error with extra fields in its body.
+    WithDetails(Box<ApiError>, Map<String, Value>),
+}
+
+impl ApiError {
+    /// Add the fields of `details`, a JSON object, to the error body.
+    pub fn with_details(self, details: Value) -> Self {
+        let Value::Object(details) = details else {
+            return self;
+        };
+
+        match self {
+            ApiError::WithDetails(error, mut fields) => {
+                fields.extend(details);
+                ApiError::WithDetails(error, fields)
+            }
+            error => ApiError::WithDetails(Box::new(error), details),
+        }
+    }
+
+    /// Stable code clients can match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Unprocessable(_) => "unprocessable",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Database(DieselError::NotFound) => "not_found",
+            ApiError::Database(DieselError::DatabaseError(kind, _)) => match kind {
+                DatabaseErrorKind::UniqueViolation => "already_exists",
+                DatabaseErrorKind::ForeignKeyViolation => "invalid_reference",
+                _ => "internal_error",
+            },
+            ApiError::Database(_) | ApiError::Internal(_) => "internal_error",
+            ApiError::WithDetails(error, _) => error.code(),
+        }
+    }
+
+    /// Message sent to the client.
+    fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Unprocessable(message)
+            | ApiError::Unavailable(message)
+            | ApiError::RateLimited { message, .. } => message.clone(),
+            ApiError::WithDetails(error, _) => error.message(),
+            error => match error.code() {
+                "not_found" => "Not found.".to_string(),
+                "already_exists" => "Already exists.".to_string(),
+                "invalid_reference" => "Refers to something that doesn't exist.".to_string(),
+                _ => "Internal server error.".to_string(),
+            },
+        }
+    }
+
+    fn details(&self) -> Option<&Map<String, Value>> {
+        match self {
+            ApiError::WithDetails(_, details) => Some(details),
+            _ => None,
+        }
+    }
+
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            ApiError::RateLimited { retry_after, .. } => Some(*retry_after),
+            ApiError::WithDetails(error, _) => error.retry_after(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Database(err) => write!(f, "database error: {err}"),
+            ApiError::Internal(message) => write!(f, "internal error: {message}"),
+            error => f.write_str(&error.message()),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self.code() {
+            "bad_request" => StatusCode::BAD_REQUEST,
+            "unauthorized" => StatusCode::UNAUTHORIZED,
+            "forbidden" => StatusCode::FORBIDDEN,
+            "not_found" => StatusCode::NOT_FOUND,
+            "conflict" | "already_exists" | "invalid_reference" => StatusCode::CONFLICT,
+            "unprocessable" => StatusCode::UNPROCESSABLE_ENTITY,
+            "rate_limited" => StatusCode::TOO_MANY_REQUESTS,
+            "unavailable" => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            log::error!("{self}");
+        }
+
+        let mut body = Map::new();
+        body.insert("code".to_string(), json!(self.code()));
+        body.insert("message".to_string(), json!(self.message()));
+        if let Some(details) = self.details() {
+            body.extend(details.clone());
+        }
+
+        let mut res = HttpResponseBuilder::new(status);
+        if let Some(retry_after) = self.retry_after() {
+            body.insert("retry_after".to_string(), json!(retry_after));
+            res.insert_header((header::RETRY_AFTER, retry_after));
+        }
+        res.json(body)
+    }
+}
+
+impl From<DieselError> for ApiError {
+    fn from(err: DieselError) -> Self {
+        ApiError::Database(err)
+    }
+}
+
+impl From<DbError> for ApiError {
+    fn from(err: DbError) -> Self {
+        match err.downcast::<DieselError>() {
+            Ok(err) => ApiError::Database(*err),
+            Err(err) => ApiError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<uuid::Error> for ApiError {
+    fn from(err: uuid::Error) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(err: BlockingError) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+impl From<SessionGetError> for ApiError {
+    fn from(err: SessionGetError) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+impl From<SessionInsertError> for ApiError {
+    fn from(err: SessionInsertError) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
@@ -0,0 +1,472 @@
+//! Content filters applied to messages before they are stored.
+//!
+//! A [`FilterChain`] runs its filters in order. Every filter that matches records a
+//! [`FilterDecision`] and then, depending on its [`FilterAction`], rejects the message, masks the
+//! offending parts or only flags it for moderators.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Upper bound for the compiled size of a regex rule, so a room owner can't make every message
+/// expensive to check.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Loose match for URLs and bare domains with a common top level domain.
+const LINK_PATTERN: &str = r"(?i)\b(?:https?://|www\.)\S+|\b[a-z0-9-]+(?:\.[a-z0-9-]+)*\.(?:com|net|org|io|dev|app|co|me|info|xyz|gg|ly)\b(?:/\S*)?";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    /// Messages longer than the given number of characters.
+    MaxLength,
+    /// Words from a comma separated list, matched case-insensitively as whole words.
+    Profanity,
+    /// URLs and domain names.
+    Links,
+    /// A custom regular expression.
+    Regex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Refuse to store the message.
+    Reject,
+    /// Store the message with the matched parts replaced by `*`, or cut to length.
+    Mask,
+    /// Store the message unchanged and record it for moderators.
+    Flag,
+}
+
+impl FilterKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterKind::MaxLength => "max_length",
+            FilterKind::Profanity => "profanity",
+            FilterKind::Links => "links",
+            FilterKind::Regex => "regex",
+        }
+    }
+}
+
+impl FilterAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterAction::Reject => "reject",
+            FilterAction::Mask => "mask",
+            FilterAction::Flag => "flag",
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for FilterAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max_length" => Ok(FilterKind::MaxLength),
+            "profanity" => Ok(FilterKind::Profanity),
+            "links" => Ok(FilterKind::Links),
+            "regex" => Ok(FilterKind::Regex),
+            _ => Err(format!("unknown filter kind {s:?}")),
+        }
+    }
+}
+
+impl FromStr for FilterAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(FilterAction::Reject),
+            "mask" => Ok(FilterAction::Mask),
+            "flag" => Ok(FilterAction::Flag),
+            _ => Err(format!("unknown filter action {s:?}")),
+        }
+    }
+}
+
+/// A filter as configured globally or for a room, before it is compiled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterRule {
+    pub kind: FilterKind,
+    /// Length limit, word list or pattern depending on `kind`; ignored for links.
+    #[serde(default)]
+    pub value: String,
+    pub action: FilterAction,
+}
+
+impl FilterRule {
+    pub fn new(kind: FilterKind, value: impl Into<String>, action: FilterAction) -> Self {
+        Self {
+            kind,
+            value: value.into(),
+            action,
+        }
+    }
+
+    /// Build the filter described by this rule, failing if its value doesn't fit its kind.
+    pub fn compile(&self) -> Result<Arc<dyn MessageFilter>, String> {
+        let filter: Arc<dyn MessageFilter> = match self.kind {
+            FilterKind::MaxLength => {
+                let max = self
+                    .value
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|max| *max > 0)
+                    .ok_or("max_length needs a positive number of characters")?;
+
+                Arc::new(MaxLength(max))
+            }
+            FilterKind::Profanity => {
+                let words: Vec<String> = self
+                    .value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|word| !word.is_empty())
+                    .map(regex::escape)
+                    .collect();
+
+                if words.is_empty() {
+                    return Err("profanity needs a comma separated list of words".to_string());
+                }
+
+                Arc::new(PatternFilter::new(
+                    FilterKind::Profanity,
+                    &format!(r"(?i)\b(?:{})\b", words.join("|")),
+                )?)
+            }
+            FilterKind::Links => Arc::new(PatternFilter::new(FilterKind::Links, LINK_PATTERN)?),
+            FilterKind::Regex => Arc::new(PatternFilter::new(FilterKind::Regex, &self.value)?),
+        };
+
+        Ok(filter)
+    }
+}
+
+/// A single check in a [`FilterChain`].
+pub trait MessageFilter: Send + Sync {
+    fn kind(&self) -> FilterKind;
+
+    /// Whether the message violates the filter.
+    fn matches(&self, message: &str) -> bool;
+
+    /// The message with the violating parts masked out.
+    fn mask(&self, message: &str) -> String;
+}
+
+struct MaxLength(usize);
+
+impl MessageFilter for MaxLength {
+    fn kind(&self) -> FilterKind {
+        FilterKind::MaxLength
+    }
+
+    fn matches(&self, message: &str) -> bool {
+        message.chars().count() > self.0
+    }
+
+    fn mask(&self, message: &str) -> String {
+        message.chars().take(self.0).collect()
+    }
+}
+
+struct PatternFilter {
+    kind: FilterKind,
+    regex: Regex,
+}
+
+impl PatternFilter {
+    fn new(kind: FilterKind, pattern: &str) -> Result<Self, String> {
+        let regex = RegexBuilder::new(pattern)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|err| format!("invalid pattern: {err}"))?;
+
+        Ok(Self { kind, regex })
+    }
+}
+
+impl MessageFilter for PatternFilter {
+    fn kind(&self) -> FilterKind {
+        self.kind
+    }
+
+    fn matches(&self, message: &str) -> bool {
+        self.regex.is_match(message)
+    }
+
+    fn mask(&self, message: &str) -> String {
+        self.regex
+            .replace_all(message, |caps: &regex::Captures| {
+                "*".repeat(caps[0].chars().count())
+            })
+            .into_owned()
+    }
+}
+
+/// A filter that matched a message and what was done about it.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FilterDecision {
+    pub kind: FilterKind,
+    pub action: FilterAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterOutcome {
+    /// The message to store, with masks applied.
+    pub message: String,
+    pub decisions: Vec<FilterDecision>,
+}
+
+impl FilterOutcome {
+    /// The filter that rejected the message, if any. Rejection stops the chain, so it is
+    /// always the last decision.
+    pub fn rejected_by(&self) -> Option<FilterKind> {
+        self.decisions
+            .last()
+            .filter(|decision| decision.action == FilterAction::Reject)
+            .map(|decision| decision.kind)
+    }
+}
+
+/// Ordered list of filters with the action to take when each one matches.
+#[derive(Clone, Default)]
+pub struct FilterChain {
+    filters: Vec<(Arc<dyn MessageFilter>, FilterAction)>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, filter: Arc<dyn MessageFilter>, action: FilterAction) {
+        self.filters.push((filter, action));
+    }
+
+    /// Compile and append a rule.
+    pub fn push_rule(&mut self, rule: &FilterRule) -> Result<(), String> {
+        self.push(rule.compile()?, rule.action);
+        Ok(())
+    }
+
+    /// Build a chain from rules, skipping (and logging) those that don't compile.
+    pub fn from_rules<'a>(rules: impl IntoIterator<Item = &'a FilterRule>) -> Self {
+        let mut chain = Self::new();
+        chain.extend(rules);
+        chain
+    }
+
+    /// Append rules, skipping (and logging) those that don't compile.
+    pub fn extend<'a>(&mut self, rules: impl IntoIterator<Item = &'a FilterRule>) {
+        for rule in rules {
+            if let Err(err) = self.push_rule(rule) {
+                log::warn!("skipping {} filter: {err}", rule.kind);
+            }
+        }
+    }
+
+    pub fn run(&self, message: String) -> FilterOutcome {
+        let mut outcome = FilterOutcome {
+            message,
+            decisions: Vec::new(),
+        };
+
+        for (filter, action) in &self.filters {
+            if !filter.matches(&outcome.message) {
+                continue;
+            }
+
+            outcome.decisions.push(FilterDecision {
+                kind: filter.kind(),
+                action: *action,
+            });
+
+            match action {
+                FilterAction::Reject => break,
+                FilterAction::Mask => outcome.message = filter.mask(&outcome.message),
+                FilterAction::Flag => {}
+            }
+        }
+
+        outcome
+    }
+}
+
+/// The global chain extended with each room's own rules, compiled once per set of rules.
+///
+/// Stored room rules are only ever added or deleted, never edited, so a room's chain is keyed
+/// by the ids of its rules; a change made by any server process shows up as a different set of
+/// ids on the next message.
+#[derive(Default)]
+pub struct FilterCache {
+    global: Arc<FilterChain>,
+    rooms: Mutex<HashMap<String, RoomChain>>,
+}
+
+struct RoomChain {
+    rule_ids: Vec<String>,
+    chain: Arc<FilterChain>,
+}
+
+impl FilterCache {
+    pub fn new(global: FilterChain) -> Self {
+        Self {
+            global: Arc::new(global),
+            rooms: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The chain for a room given its stored rules as `(id, rule)` pairs: global rules first,
+    /// then the room's own.
+    pub fn room_chain(&self, room_id: &str, rules: Vec<(String, FilterRule)>) -> Arc<FilterChain> {
+        if rules.is_empty() {
+            self.forget(room_id);
+            return self.global.clone();
+        }
+
+        let rule_ids: Vec<String> = rules.iter().map(|(id, _)| id.clone()).collect();
+        if let Some(room) = self.rooms.lock().unwrap().get(room_id) {
+            if room.rule_ids == rule_ids {
+                return room.chain.clone();
+            }
+        }
+
+        // compile without holding the lock; a racing request at worst compiles the same rules
+        let mut chain = FilterChain::clone(&self.global);
+        chain.extend(rules.iter().map(|(_, rule)| rule));
+        let chain = Arc::new(chain);
+
+        self.rooms.lock().unwrap().insert(
+            room_id.to_string(),
+            RoomChain {
+                rule_ids,
+                chain: chain.clone(),
+            },
+        );
+
+        chain
+    }
+
+    /// Drop the compiled chain of a room that is gone.
+    pub fn forget(&self, room_id: &str) {
+        self.rooms.lock().unwrap().remove(room_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(rules: &[FilterRule]) -> FilterChain {
+        FilterChain::from_rules(rules)
+    }
+
+    #[test]
+    fn masks_whole_words_case_insensitively() {
+        let chain = chain(&[FilterRule::new(
+            FilterKind::Profanity,
+            "darn, heck",
+            FilterAction::Mask,
+        )]);
+
+        let outcome = chain.run("Darn it, what the heck, darnation".to_string());
+        assert_eq!(outcome.message, "**** it, what the ****, darnation");
+        assert_eq!(outcome.rejected_by(), None);
+        assert_eq!(outcome.decisions.len(), 1);
+    }
+
+    #[test]
+    fn masks_by_cutting_to_length() {
+        let chain = chain(&[FilterRule::new(
+            FilterKind::MaxLength,
+            "5",
+            FilterAction::Mask,
+        )]);
+
+        assert_eq!(chain.run("héllo world".to_string()).message, "héllo");
+        assert!(chain.run("short".to_string()).decisions.is_empty());
+    }
+
+    #[test]
+    fn reject_stops_the_chain() {
+        let chain = chain(&[
+            FilterRule::new(FilterKind::Links, "", FilterAction::Reject),
+            FilterRule::new(FilterKind::Regex, "spam", FilterAction::Flag),
+        ]);
+
+        let outcome = chain.run("spam at example.com".to_string());
+        assert_eq!(outcome.rejected_by(), Some(FilterKind::Links));
+        assert_eq!(outcome.decisions.len(), 1);
+
+        let outcome = chain.run("just spam".to_string());
+        assert_eq!(outcome.rejected_by(), None);
+        assert_eq!(outcome.message, "just spam");
+        assert_eq!(outcome.decisions[0].action, FilterAction::Flag);
+    }
+
+    #[test]
+    fn skips_rules_that_dont_compile() {
+        let chain = chain(&[
+            FilterRule::new(FilterKind::Regex, "(", FilterAction::Reject),
+            FilterRule::new(FilterKind::MaxLength, "0", FilterAction::Reject),
+            FilterRule::new(FilterKind::Profanity, " , ", FilterAction::Reject),
+            FilterRule::new(FilterKind::Regex, "b+", FilterAction::Mask),
+        ]);
+
+        let outcome = chain.run("abbc".to_string());
+        assert_eq!(outcome.message, "a**c");
+        assert_eq!(outcome.decisions.len(), 1);
+    }
+
+    #[test]
+    fn cache_recompiles_only_when_the_rules_change() {
+        let cache = FilterCache::new(chain(&[FilterRule::new(
+            FilterKind::Regex,
+            "a",
+            FilterAction::Mask,
+        )]));
+        let rule = |id: &str, pattern: &str| {
+            (
+                id.to_string(),
+                FilterRule::new(FilterKind::Regex, pattern, FilterAction::Mask),
+            )
+        };
+
+        let first = cache.room_chain("room", vec![rule("1", "b")]);
+        assert_eq!(first.run("abc".to_string()).message, "**c");
+        assert!(Arc::ptr_eq(
+            &first,
+            &cache.room_chain("room", vec![rule("1", "b")])
+        ));
+
+        let second = cache.room_chain("room", vec![rule("1", "b"), rule("2", "c")]);
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(second.run("abc".to_string()).message, "***");
+
+        let global = cache.room_chain("room", Vec::new());
+        assert_eq!(global.run("abc".to_string()).message, "*bc");
+        assert!(cache.rooms.lock().unwrap().is_empty());
+    }
+}
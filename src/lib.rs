@@ -0,0 +1,30 @@
+use bytestring::ByteString;
+
+pub mod backplane;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod filters;
+pub mod login_guard;
+pub mod routes;
+pub mod services;
+
+pub mod middlewares;
+pub mod models;
+pub mod password;
+pub mod rate_limit;
+pub mod resume;
+pub mod schema;
+pub mod server;
+// mod session;
+pub mod totp;
+
+pub mod types;
+pub mod utils;
+
+pub type ConnId = usize;
+pub type RoomId = String;
+/// An outgoing frame. Cloning it only bumps a reference count, so a frame is serialized once
+/// and shared by every connection it is sent to.
+pub type Msg = ByteString;
+pub type UserId = String;
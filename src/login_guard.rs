@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::LoginConfig;
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// Tracks failed sign in attempts per account and per client IP.
+///
+/// After `free_attempts` failures every further failure blocks the key for an exponentially
+/// growing delay, and once `lockout_threshold` is reached the key is locked out for
+/// `lockout_secs`. Counters are forgotten once a key has been quiet for the lockout duration.
+///
+/// An attempt counts as failed from the moment it starts, so guesses sent in parallel can't
+/// all get in before the first one fails; [`refund`](Self::refund) takes it back once the
+/// credentials turn out right.
+#[derive(Debug)]
+pub struct LoginGuard {
+    config: LoginConfig,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl LoginGuard {
+    pub fn new(config: LoginConfig) -> Self {
+        Self {
+            config,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn account_key(username: &str) -> String {
+        format!("user:{}", username.to_lowercase())
+    }
+
+    fn ip_key(ip: &str) -> String {
+        format!("ip:{ip}")
+    }
+
+    /// Start a sign in attempt, counting it as failed until it is refunded.
+    ///
+    /// Returns how long the caller has to wait instead if either the account or the IP is
+    /// blocked; such attempts aren't counted.
+    pub fn begin_attempt(&self, username: &str, ip: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+
+        self.prune(&mut failures, now);
+
+        let keys = [Self::account_key(username), Self::ip_key(ip)];
+
+        let wait = keys
+            .iter()
+            .filter_map(|key| failures.get(key)?.blocked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max();
+        if let Some(wait) = wait {
+            return Err(wait);
+        }
+
+        for key in keys {
+            let entry = failures.entry(key).or_insert(Failures {
+                count: 0,
+                last_failure: now,
+                blocked_until: None,
+            });
+
+            entry.count += 1;
+            entry.last_failure = now;
+            entry.blocked_until = self.block_duration(entry.count).map(|delay| now + delay);
+        }
+
+        Ok(())
+    }
+
+    /// Take back an attempt started with [`begin_attempt`](Self::begin_attempt) whose
+    /// credentials were right.
+    pub fn refund(&self, username: &str, ip: &str) {
+        let mut failures = self.failures.lock().unwrap();
+
+        for key in [Self::account_key(username), Self::ip_key(ip)] {
+            if let Some(entry) = failures.get_mut(&key) {
+                entry.count = entry.count.saturating_sub(1);
+                // a block set by another attempt in the meantime stays
+                if self.block_duration(entry.count).is_none() {
+                    entry.blocked_until = None;
+                }
+            }
+        }
+    }
+
+    /// Clear the account counter after a successful sign in.
+    ///
+    /// The IP counter is kept so a valid account can't be used to reset it.
+    pub fn record_success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .remove(&Self::account_key(username));
+    }
+
+    fn block_duration(&self, count: u32) -> Option<Duration> {
+        let config = &self.config;
+
+        if count >= config.lockout_threshold {
+            return Some(Duration::from_secs(config.lockout_secs));
+        }
+
+        if count <= config.free_attempts {
+            return None;
+        }
+
+        let exponent = (count - config.free_attempts - 1).min(31);
+        let delay = config
+            .base_backoff_secs
+            .saturating_mul(1 << exponent)
+            .min(config.max_backoff_secs);
+
+        Some(Duration::from_secs(delay))
+    }
+
+    fn prune(&self, failures: &mut HashMap<String, Failures>, now: Instant) {
+        let forget_after = Duration::from_secs(self.config.lockout_secs);
+
+        failures.retain(|_, entry| {
+            now.duration_since(entry.last_failure) < forget_after
+                || entry.blocked_until.is_some_and(|until| until > now)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> LoginGuard {
+        LoginGuard::new(LoginConfig {
+            free_attempts: 3,
+            base_backoff_secs: 2,
+            max_backoff_secs: 10,
+            lockout_threshold: 8,
+            lockout_secs: 600,
+        })
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_then_locks_out() {
+        let guard = guard();
+        let delays: Vec<_> = (1..=9)
+            .map(|count| guard.block_duration(count).map(|delay| delay.as_secs()))
+            .collect();
+
+        assert_eq!(
+            delays,
+            [
+                None,
+                None,
+                None,
+                Some(2),
+                Some(4),
+                Some(8),
+                Some(10),
+                Some(600),
+                Some(600)
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_once_the_free_attempts_are_used() {
+        let guard = guard();
+
+        // the attempt past the free ones still goes through, but blocks the next one
+        for _ in 0..4 {
+            assert!(guard.begin_attempt("alice", "10.0.0.1").is_ok());
+        }
+
+        let wait = guard.begin_attempt("alice", "10.0.0.1").unwrap_err();
+        assert!(wait <= Duration::from_secs(2));
+
+        // the account is blocked from other addresses, the address for other accounts
+        assert!(guard.begin_attempt("Alice", "10.0.0.2").is_err());
+        assert!(guard.begin_attempt("bob", "10.0.0.1").is_err());
+        assert!(guard.begin_attempt("bob", "10.0.0.2").is_ok());
+    }
+
+    #[test]
+    fn refunded_attempts_dont_count() {
+        let guard = guard();
+
+        for _ in 0..10 {
+            guard.begin_attempt("alice", "10.0.0.1").unwrap();
+            guard.refund("alice", "10.0.0.1");
+        }
+
+        let failures = guard.failures.lock().unwrap();
+        assert_eq!(failures[&LoginGuard::account_key("alice")].count, 0);
+        assert_eq!(failures[&LoginGuard::ip_key("10.0.0.1")].count, 0);
+    }
+
+    #[test]
+    fn success_clears_the_account_but_not_the_ip() {
+        let guard = guard();
+
+        for _ in 0..4 {
+            guard.begin_attempt("alice", "10.0.0.1").unwrap();
+        }
+        guard.record_success("alice");
+
+        assert!(guard.begin_attempt("alice", "10.0.0.2").is_ok());
+        assert!(guard.begin_attempt("bob", "10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn locks_out_at_the_threshold() {
+        let guard = guard();
+        let mut failures = guard.failures.lock().unwrap();
+        let now = Instant::now();
+
+        failures.insert(
+            LoginGuard::account_key("alice"),
+            Failures {
+                count: 7,
+                last_failure: now,
+                blocked_until: None,
+            },
+        );
+        drop(failures);
+
+        guard.begin_attempt("alice", "10.0.0.1").unwrap();
+
+        let wait = guard.begin_attempt("alice", "10.0.0.1").unwrap_err();
+        assert!(wait > Duration::from_secs(590));
+    }
+}
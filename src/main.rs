@@ -1,44 +1,36 @@
-#![allow(unused)]
 use actix_cors::Cors;
-use actix_files::{Files, NamedFile};
 use actix_session::{
-    config::PersistentSession, storage::CookieSessionStore, Session, SessionExt, SessionMiddleware,
-};
-use actix_web::{
-    cookie::Key,
-    dev::{Service, ServiceRequest, ServiceResponse},
-    get, http, middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer,
+    config::PersistentSession, storage::CookieSessionStore, Session, SessionMiddleware,
 };
+use actix_web::{cookie::Key, get, http, middleware, web, App, HttpServer};
 use actix_web_lab::web::spa;
 use diesel::{
     prelude::*,
     r2d2::{self, ConnectionManager},
 };
 use env_logger::Env;
-use middlewares::auth::Authentication;
-use models::Conversation;
-use routes::{create_auth_scope, create_conversation_scope, create_room_scope};
-use server::ChatServer;
-use tokio::{task::spawn, try_join};
-use uuid::Uuid;
-
-mod db;
-mod routes;
-mod services;
-
-mod middlewares;
-mod models;
-mod schema;
-mod server;
-// mod session;
-
-mod types;
-mod utils;
-
-pub type ConnId = usize;
-pub type RoomId = String;
-pub type Msg = String;
-pub type UserId = String;
+use rust_react_chat::{
+    backplane::{Backplane, MemoryBackplane, TcpBackplane},
+    config::Config,
+    db,
+    error::ApiError,
+    filters::{FilterCache, FilterChain},
+    login_guard::LoginGuard,
+    middlewares::auth::Authentication,
+    password::{LogDelivery, ResetTokenDelivery},
+    rate_limit::RateLimiter,
+    routes::{
+        self, create_admin_scope, create_auth_scope, create_conversation_scope, create_room_scope,
+        create_user_scope,
+    },
+    server::{ChatServer, ChatServerHandle},
+    types::DbPool,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    task::{spawn, JoinHandle},
+    time::{sleep, timeout},
+};
 
 #[get("/hello")]
 async fn hello(session: Session) -> String {
@@ -54,29 +46,125 @@ async fn auth(session: Session) -> String {
     "world".to_string()
 }
 
+/// Promote the configured user to site admin if the site has none yet.
+fn bootstrap_admin(pool: &DbPool, username: &str) {
+    let res = pool
+        .get()
+        .map_err(db::DbError::from)
+        .and_then(|mut conn| db::users::bootstrap_admin(&mut conn, username));
+
+    match res {
+        Ok(true) => log::info!("promoted {username:?} to site admin"),
+        Ok(false) => log::info!("not promoting {username:?}: an admin exists or the user doesn't"),
+        Err(err) => log::error!("failed to bootstrap site admin {username:?}: {err}"),
+    }
+}
+
+/// Wait for SIGTERM or Ctrl-C.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                log::error!("can't listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Send every socket away with a hint to reconnect, finish the HTTP requests in flight and wait
+/// for their database writes.
+async fn shutdown(
+    server: actix_web::dev::ServerHandle,
+    app: &mut JoinHandle<std::io::Result<()>>,
+    chat_server: ChatServerHandle,
+    pool: DbPool,
+) {
+    chat_server.shutdown().await;
+    log::info!("closed every socket");
+
+    server.stop(true).await;
+    match app.await {
+        Ok(Ok(())) => log::info!("finished the HTTP requests in flight"),
+        Ok(Err(err)) => log::error!("HTTP server failed while stopping: {err}"),
+        Err(err) => log::error!("HTTP server task failed: {err}"),
+    }
+
+    // queries run on the blocking thread pool with a connection checked out of the pool
+    loop {
+        let state = pool.state();
+        if state.idle_connections == state.connections {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
 // #[actix_web::main]
-#[tokio::main(flavor = "current_thread")]
+#[tokio::main]
 async fn main() -> std::io::Result<()> {
     let conn_spec = "chat.db";
     let manager = ConnectionManager::<SqliteConnection>::new(conn_spec);
     let pool = r2d2::Pool::builder()
+        .connection_customizer(Box::new(db::ConnectionOptions))
         .build(manager)
         .expect("Failed to create pool.");
     let server_addr = "127.0.0.1";
-    let server_port = 8080;
 
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
-    let (chat_server, server_tx) = ChatServer::new(pool.clone());
+    let config = Config::from_env();
+    let server_port = config.chat.port;
+
+    if !config.admin.bootstrap_username.is_empty() {
+        bootstrap_admin(&pool, &config.admin.bootstrap_username);
+    }
+    let reset_delivery: Arc<dyn ResetTokenDelivery> = Arc::new(LogDelivery);
+    let login_guard = web::Data::new(LoginGuard::new(config.login.clone()));
+    let rate_limiter = web::Data::new(RateLimiter::new());
+    let filters = web::Data::new(FilterCache::new(FilterChain::from_rules(
+        &config.filters.rules(),
+    )));
+
+    let backplane: Arc<dyn Backplane> = if config.chat.backplane_addr.is_empty() {
+        Arc::new(MemoryBackplane::new())
+    } else {
+        Arc::new(TcpBackplane::connect(config.chat.backplane_addr.clone()))
+    };
+
+    let (shards, server_tx) = ChatServer::new(
+        pool.clone(),
+        config.chat.shards,
+        config.resume.event_retention,
+        backplane,
+    );
+
+    let mut chat_server: Vec<_> = shards.into_iter().map(|shard| spawn(shard.run())).collect();
+    chat_server.push(spawn(server_tx.clone().relay()));
 
-    let chat_server = spawn(chat_server.run());
+    let shutdown_timeout = config.chat.shutdown_timeout_secs;
+    let shutdown_chat_server = server_tx.clone();
+    let shutdown_pool = pool.clone();
 
     let app = HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:3000")
             .allowed_origin("http://localhost:5173")
             .allowed_origin("http://localhost:8080")
-            .allowed_methods(vec!["GET", "POST"])
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
             .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
             .allowed_header(http::header::CONTENT_TYPE)
             .max_age(3600);
@@ -84,16 +172,37 @@ async fn main() -> std::io::Result<()> {
         let auth_scope = create_auth_scope();
         let room_scope = create_room_scope();
         let conversation_scope = create_conversation_scope();
+        let user_scope = create_user_scope();
+        let admin_scope = create_admin_scope();
 
         let api_scope = web::scope("/api")
             .service(hello)
             .service(auth_scope)
             .service(room_scope)
-            .service(conversation_scope);
+            .service(conversation_scope)
+            .service(user_scope)
+            .service(admin_scope);
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(server_tx.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::from(reset_delivery.clone()))
+            .app_data(login_guard.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(filters.clone())
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+            )
+            .app_data(
+                web::PathConfig::default()
+                    .error_handler(|err, _| ApiError::NotFound(err.to_string()).into()),
+            )
             .wrap(Authentication)
             .wrap(
                 SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
@@ -108,20 +217,55 @@ async fn main() -> std::io::Result<()> {
             .wrap(middleware::Logger::default())
             .service(web::resource("/ws").route(web::get().to(routes::ws::chat_ws)))
             .service(api_scope)
-            .service(spa()
-                .index_file("./static/index.html")
-                .static_resources_mount("/")
-                .static_resources_location("./static")
-                .finish())
+            .service(
+                spa()
+                    .index_file("./static/index.html")
+                    .static_resources_mount("/")
+                    .static_resources_location("./static")
+                    .finish(),
+            )
             .wrap(middleware::NormalizePath::trim())
     })
     .workers(2)
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
     .bind((server_addr, server_port))?
     .run();
+    let app_handle = app.handle();
+    // keep the server running when the signal arrives, until it's stopped below
+    let mut app = spawn(app);
 
     log::info!("Server running at http://{server_addr}:{server_port}");
 
-    try_join!(app, async move { chat_server.await.unwrap() })?;
+    tokio::select! {
+        res = &mut app => return res.unwrap(),
+        res = async move {
+            for shard in chat_server {
+                shard.await.unwrap()?;
+            }
+            Ok::<_, std::io::Error>(())
+        } => return res,
+        _ = shutdown_signal() => {}
+    }
+
+    let deadline = Duration::from_secs(shutdown_timeout);
+    log::info!("shutting down, within {deadline:?}");
+
+    if timeout(
+        deadline,
+        shutdown(
+            app_handle.clone(),
+            &mut app,
+            shutdown_chat_server,
+            shutdown_pool,
+        ),
+    )
+    .await
+    .is_err()
+    {
+        log::warn!("graceful shutdown took longer than {deadline:?}; exiting anyway");
+        app_handle.stop(false).await;
+    }
 
     Ok(())
 }
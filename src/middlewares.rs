@@ -1 +1 @@
-pub mod auth;
\ No newline at end of file
+pub mod auth;
@@ -1,22 +1,23 @@
 use actix_session::SessionExt;
 use actix_web::{
-    body::{BoxBody, EitherBody},
+    body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    error::ErrorUnauthorized,
-    http::{self, StatusCode},
-    web::Json,
-    Error, HttpResponse,
+    web, Error, ResponseError,
 };
 use futures_util::future::LocalBoxFuture;
-use serde_json::json;
-use std::future::{ready, Ready};
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
 use uuid::Uuid;
 
+use crate::{db, error::ApiError, routes::auth::check_suspended, types::DbPool};
+
 pub struct Authentication;
 
 impl<S, B> Transform<S, ServiceRequest> for Authentication
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -27,12 +28,14 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(AuthenticationMiddleware { service }))
+        ready(Ok(AuthenticationMiddleware {
+            service: Rc::new(service),
+        }))
     }
 }
 
 pub struct AuthenticationMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 fn is_public_path(path: &str) -> bool {
@@ -49,7 +52,7 @@ fn is_public_path(path: &str) -> bool {
 
 impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -60,29 +63,62 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let path = req.path();
+        if is_public_path(req.path()) {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                let tmp: ServiceResponse<B> = fut.await?;
+                Ok(tmp.map_into_left_body())
+            });
+        }
+
+        let session = req.get_session();
+        let user_id = session.get::<Uuid>("user_id").unwrap_or(None);
+
+        // a sign in waiting for its second factor is not authenticated yet
+        let pending_2fa = session.entries().contains_key("pending_2fa");
+
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+        let service = Rc::clone(&self.service);
 
-        let user_id = req.get_session().get::<Uuid>("user_id").unwrap_or(None);
+        Box::pin(async move {
+            let user = match (user_id, pool) {
+                (Some(user_id), Some(pool)) if !pending_2fa => web::block(move || {
+                    let mut conn = pool.get()?;
+                    db::users::find_user_by_uid(&mut conn, user_id)
+                })
+                .await?
+                .map_err(ApiError::from)?,
+                _ => None,
+            };
+
+            if user.is_none() {
+                // the account behind this session no longer exists
+                if user_id.is_some() && !pending_2fa {
+                    session.purge();
+                }
 
-        if user_id.is_none() && !is_public_path(path) {
-            Box::pin(async move {
                 let request = req.into_parts().0;
-                let response = HttpResponse::Unauthorized()
-                    .json(json!({
-                        "message":"Signin required."
-                    }))
+                let response = ApiError::Unauthorized("Signin required.".to_string())
+                    .error_response()
                     .map_into_right_body();
 
                 let res = ServiceResponse::new(request, response);
 
-                Ok(res)
-            })
-        } else {
-            let fut = self.service.call(req);
-            Box::pin(async move {
-                let tmp: ServiceResponse<B> = fut.await?;
-                Ok(tmp.map_into_left_body())
-            })
-        }
+                return Ok(res);
+            }
+
+            if let Some(Err(err)) = user.as_ref().map(check_suspended) {
+                session.purge();
+
+                let request = req.into_parts().0;
+                return Ok(ServiceResponse::new(
+                    request,
+                    err.error_response().map_into_right_body(),
+                ));
+            }
+
+            let tmp: ServiceResponse<B> = service.call(req).await?;
+            Ok(tmp.map_into_left_body())
+        })
     }
 }
@@ -1,6 +1,7 @@
+use crate::filters::FilterRule;
 use crate::schema::*;
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
 // db models
 #[derive(
@@ -13,6 +14,22 @@ pub struct User {
     pub password: String,
     #[serde(skip_serializing)]
     pub created_at: String,
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+    #[serde(skip_serializing)]
+    pub totp_enabled: bool,
+    #[serde(skip_serializing)]
+    pub totp_last_step: Option<i64>,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub bio: Option<String>,
+    pub status_message: Option<String>,
+    #[serde(skip_serializing)]
+    pub is_admin: bool,
+    #[serde(skip_serializing)]
+    pub suspended_at: Option<String>,
+    #[serde(skip_serializing)]
+    pub suspended_reason: Option<String>,
 }
 
 #[derive(
@@ -47,6 +64,15 @@ pub struct Room {
     pub last_message: String,
     pub created_at: String,
     pub owner_id: String,
+    pub slow_mode_seconds: i32,
+    /// Set while the room is archived.
+    pub archived_at: Option<String>,
+}
+
+impl Room {
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
 }
 
 #[derive(Identifiable, Selectable, Insertable, Queryable, Associations, Debug, Clone)]
@@ -57,6 +83,158 @@ pub struct Room {
 pub struct RoomUser {
     pub room_id: String,
     pub user_id: String,
+    pub role: String,
+}
+
+impl RoomUser {
+    pub const MEMBER: &'static str = "member";
+    pub const MODERATOR: &'static str = "moderator";
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, Associations, Insertable)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = password_reset_tokens)]
+pub struct PasswordResetToken {
+    pub id: String,
+    pub user_id: String,
+    pub token_hash: String,
+    pub expires_at: String,
+    pub used_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = failed_logins)]
+pub struct FailedLogin {
+    pub id: String,
+    pub username: String,
+    pub user_id: Option<String>,
+    pub ip: String,
+    pub reason: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, Associations, Insertable)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = recovery_codes)]
+pub struct RecoveryCode {
+    pub id: String,
+    pub user_id: String,
+    pub code_hash: String,
+    pub used_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = room_filters)]
+pub struct RoomFilter {
+    pub id: String,
+    pub room_id: String,
+    pub kind: String,
+    pub value: String,
+    pub action: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = filter_decisions)]
+pub struct FilterDecisionRecord {
+    pub id: String,
+    pub room_id: String,
+    pub user_id: String,
+    pub conversation_id: Option<String>,
+    pub kind: String,
+    pub action: String,
+    pub message: String,
+    pub created_at: String,
+}
+
+impl RoomFilter {
+    /// The stored rule, or `None` if the row holds an unknown kind or action.
+    pub fn to_rule(&self) -> Option<FilterRule> {
+        Some(FilterRule::new(
+            self.kind.parse().ok()?,
+            self.value.clone(),
+            self.action.parse().ok()?,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = reports)]
+pub struct Report {
+    pub id: String,
+    pub room_id: String,
+    pub reporter_id: String,
+    pub reported_user_id: String,
+    pub conversation_id: Option<String>,
+    pub reason: String,
+    pub status: String,
+    pub resolution: Option<String>,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<String>,
+    pub created_at: String,
+}
+
+impl Report {
+    pub const OPEN: &'static str = "open";
+    pub const RESOLVED: &'static str = "resolved";
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = room_bans)]
+#[diesel(primary_key(room_id, user_id))]
+pub struct RoomBan {
+    pub room_id: String,
+    pub user_id: String,
+    pub banned_by: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = audit_events)]
+pub struct AuditEvent {
+    pub id: String,
+    pub actor_id: Option<String>,
+    pub action: String,
+    pub room_id: Option<String>,
+    pub target_user_id: Option<String>,
+    /// JSON object with action specific details.
+    #[serde(serialize_with = "serialize_json_text")]
+    pub details: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = user_blocks)]
+#[diesel(primary_key(blocker_id, blocked_id))]
+pub struct UserBlock {
+    pub blocker_id: String,
+    pub blocked_id: String,
+    pub created_at: String,
+}
+
+/// A frame sent to a room, kept so reconnecting clients can catch up on what they missed.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = room_events)]
+#[diesel(primary_key(room_id, seq))]
+pub struct RoomEvent {
+    pub room_id: String,
+    /// Numbers the room's events from 1, without gaps.
+    pub seq: i64,
+    pub sender_id: Option<String>,
+    pub payload: String,
+    pub created_at: String,
+    /// The message the event carries, if it is a message.
+    pub conversation_id: Option<String>,
+}
+
+/// Serialize a column holding JSON text as the JSON value itself.
+fn serialize_json_text<S: Serializer>(text: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(value) => value.serialize(serializer),
+        Err(_) => serializer.serialize_str(text),
+    }
 }
 
 // business models
@@ -67,6 +245,73 @@ pub struct NewUser {
     pub sign_in: bool,
 }
 
+/// Profile fields to change. `None` leaves a field untouched, `Some(None)` clears it.
+#[derive(Debug, Clone, Default, AsChangeset)]
+#[diesel(table_name = users)]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub avatar_url: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub status_message: Option<Option<String>>,
+}
+
+/// What happens to rooms owned by an account that is being deleted.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomPolicy {
+    /// Hand the room over to another member, archiving it if there is none.
+    #[default]
+    Transfer,
+    /// Archive the room, so a site admin can delete it for good after the grace period.
+    Delete,
+}
+
+/// What happens to messages written by an account that is being deleted.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessagePolicy {
+    /// Keep the messages but attribute them to the shared "deleted user".
+    #[default]
+    Anonymize,
+    Delete,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeletedAccount {
+    /// Rooms archived because of the account's deletion.
+    pub archived_rooms: Vec<Room>,
+    /// Rooms handed over to a new owner.
+    pub transferred_rooms: Vec<Room>,
+    /// Other rooms the account was a member of.
+    pub left_rooms: Vec<String>,
+}
+
+/// A user as site admins see them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminUserResponse {
+    pub id: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub created_at: String,
+    pub is_admin: bool,
+    pub suspended_at: Option<String>,
+    pub suspended_reason: Option<String>,
+}
+
+impl From<User> for AdminUserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            created_at: user.created_at,
+            is_admin: user.is_admin,
+            suspended_at: user.suspended_at,
+            suspended_reason: user.suspended_reason,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewConversation {
     pub user_id: String,
@@ -84,6 +329,44 @@ pub struct ListRoomResponse {
 pub struct RoomResponse {
     pub room: Room,
     pub users: Vec<User>,
+    pub moderator_ids: Vec<String>,
     pub conversations: Vec<Conversation>,
     pub exited_users: Vec<User>,
 }
+
+/// How a moderator resolves a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportAction {
+    /// Close the report without doing anything.
+    Dismiss,
+    /// Delete the reported message.
+    DeleteMessage,
+    /// Remove the reported user from the room.
+    Kick,
+    /// Remove the reported user from the room and keep them from joining again.
+    Ban,
+}
+
+impl ReportAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportAction::Dismiss => "dismiss",
+            ReportAction::DeleteMessage => "delete_message",
+            ReportAction::Kick => "kick",
+            ReportAction::Ban => "ban",
+        }
+    }
+}
+
+/// An open report together with what a moderator needs to judge it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportResponse {
+    pub report: Report,
+    pub reporter: Option<User>,
+    pub reported_user: Option<User>,
+    /// The reported message, if it still exists.
+    pub message: Option<Conversation>,
+    /// Messages around the reported one, oldest first.
+    pub context: Vec<Conversation>,
+}
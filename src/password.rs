@@ -0,0 +1,93 @@
+use std::sync::OnceLock;
+
+use bcrypt::BcryptResult;
+use rand::{distributions::Alphanumeric, thread_rng, Rng as _};
+use sha2::{Digest, Sha256};
+
+use crate::{config::PasswordConfig, models::User};
+
+pub fn hash_password(password: &str, cost: u32) -> BcryptResult<String> {
+    bcrypt::hash(password, cost)
+}
+
+/// Whether a stored bcrypt hash was produced with a different cost than the configured one.
+pub fn needs_rehash(hashed: &str, cost: u32) -> bool {
+    // bcrypt hashes look like `$2b$04$<salt+hash>`
+    hashed.split('$').nth(2).and_then(|c| c.parse::<u32>().ok()) != Some(cost)
+}
+
+/// Run a bcrypt verification against a throwaway hash.
+///
+/// Used when the user doesn't exist so that the response takes as long as a wrong password.
+pub fn dummy_verify(password: &str, cost: u32) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let hashed =
+        DUMMY_HASH.get_or_init(|| hash_password("dummy password", cost).unwrap_or_default());
+    let _ = bcrypt::verify(password, hashed);
+}
+
+/// Check a new password against the minimum password rules.
+///
+/// Returns the first violated rule as a user facing message.
+pub fn validate_password(
+    config: &PasswordConfig,
+    username: &str,
+    password: &str,
+) -> Result<(), String> {
+    if password.chars().count() < config.min_length {
+        return Err(format!(
+            "Password must be at least {} characters long.",
+            config.min_length
+        ));
+    }
+
+    if !password.chars().any(|c| c.is_alphabetic()) {
+        return Err("Password must contain at least one letter.".to_string());
+    }
+
+    if !password.chars().any(|c| c.is_ascii_digit()) {
+        return Err("Password must contain at least one digit.".to_string());
+    }
+
+    if password.eq_ignore_ascii_case(username) {
+        return Err("Password must not be the same as the username.".to_string());
+    }
+
+    Ok(())
+}
+
+/// Generate a random password reset token. Only its hash is persisted.
+pub fn generate_reset_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect()
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Delivers password reset tokens to their owners.
+///
+/// The server only generates and stores tokens; how they reach the user (email, SMS, ...) is up
+/// to the implementation registered in `main`.
+pub trait ResetTokenDelivery: Send + Sync {
+    fn deliver(&self, user: &User, token: &str);
+}
+
+/// Development delivery which writes reset tokens to the server log.
+pub struct LogDelivery;
+
+impl ResetTokenDelivery for LogDelivery {
+    fn deliver(&self, user: &User, token: &str) {
+        log::info!(
+            "password reset token for {} ({}): {}",
+            user.username,
+            user.id,
+            token
+        );
+    }
+}
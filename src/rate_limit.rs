@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::error::ApiError;
+
+/// Size of a token bucket: `burst` tokens, refilled evenly over `per_secs` seconds.
+///
+/// Parsed from `<burst>/<per_secs>`, e.g. `10/10` for ten requests in a burst
+/// and one more every second after that.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    burst: u32,
+    per_secs: u32,
+}
+
+impl RateLimit {
+    /// Panics if `burst` or `per_secs` is 0.
+    pub const fn new(burst: u32, per_secs: u32) -> Self {
+        assert!(
+            burst > 0 && per_secs > 0,
+            "a rate limit needs a burst and seconds of at least 1"
+        );
+
+        Self { burst, per_secs }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.burst as f64 / self.per_secs as f64
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (burst, per_secs) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected <burst>/<seconds>, got {s:?}"))?;
+
+        let burst = burst.trim().parse().map_err(|_| "invalid burst")?;
+        let per_secs = per_secs.trim().parse().map_err(|_| "invalid seconds")?;
+
+        // an empty bucket would never refill
+        if burst == 0 || per_secs == 0 {
+            return Err(format!("burst and seconds must be at least 1, got {s:?}"));
+        }
+
+        Ok(Self { burst, per_secs })
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.refill_per_sec()).min(self.limit.burst as f64);
+        self.updated_at = now;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    checks: u64,
+}
+
+/// Number of checks between sweeps of buckets that have refilled completely.
+const PRUNE_EVERY: u64 = 1024;
+
+/// Token bucket rate limiter shared by all workers.
+///
+/// Keys are free-form strings such as `message:user:<id>`; every key gets its own bucket.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take one token from the bucket of `key`.
+    ///
+    /// Returns how long to wait before the next token is available if the bucket is empty.
+    pub fn check(&self, key: &str, limit: RateLimit) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        buckets.checks += 1;
+        if buckets.checks.is_multiple_of(PRUNE_EVERY) {
+            // a full bucket behaves exactly like a missing one, so it can be dropped
+            buckets.buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                bucket.tokens < bucket.limit.burst as f64
+            });
+        }
+
+        let bucket = buckets.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            updated_at: now,
+            limit,
+        });
+
+        bucket.limit = limit;
+        bucket.refill(now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / limit.refill_per_sec()))
+        }
+    }
+
+    /// Like [`RateLimiter::check`], but fails with a `429 Too Many Requests` error.
+    pub fn check_http(&self, key: &str, limit: RateLimit) -> Result<(), ApiError> {
+        self.check(key, limit).map_err(|retry_after| {
+            let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+
+            ApiError::RateLimited {
+                message: format!("Too many requests. Try again in {retry_after} seconds."),
+                retry_after,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(limit: RateLimit, tokens: f64, updated_at: Instant) -> Bucket {
+        Bucket {
+            tokens,
+            updated_at,
+            limit,
+        }
+    }
+
+    #[test]
+    fn parses_burst_and_seconds() {
+        let limit: RateLimit = "10/5".parse().unwrap();
+        assert_eq!((limit.burst, limit.per_secs), (10, 5));
+        assert_eq!(limit.refill_per_sec(), 2.0);
+
+        assert!("10".parse::<RateLimit>().is_err());
+        assert!("0/10".parse::<RateLimit>().is_err());
+        assert!("10/0".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn refills_evenly_up_to_the_burst() {
+        let start = Instant::now();
+        let mut bucket = bucket(RateLimit::new(10, 10), 0.0, start);
+
+        bucket.refill(start + Duration::from_millis(2_500));
+        assert!((bucket.tokens - 2.5).abs() < 1e-9);
+
+        bucket.refill(start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 10.0);
+    }
+
+    #[test]
+    fn refills_slower_than_one_token_a_second() {
+        let start = Instant::now();
+        let mut bucket = bucket(RateLimit::new(1, 4), 0.0, start);
+
+        bucket.refill(start + Duration::from_secs(2));
+        assert!((bucket.tokens - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_bucket_reports_the_wait() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit::new(2, 10);
+
+        assert!(limiter.check("key", limit).is_ok());
+        assert!(limiter.check("key", limit).is_ok());
+
+        let wait = limiter.check("key", limit).unwrap_err();
+        assert!(wait > Duration::from_millis(4_900) && wait <= Duration::from_secs(5));
+
+        // other keys have their own bucket
+        assert!(limiter.check("other", limit).is_ok());
+    }
+}
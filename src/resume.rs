@@ -0,0 +1,118 @@
+//! Resume tokens, handed out in the `init` frame so a client that reconnects can ask for the
+//! room events it missed.
+//!
+//! A token is `<conn_id>.<user_id>.<issued_at>.<signature>`, signed with HMAC-SHA256. Any server
+//! process with the same secret can check it without keeping state.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore as _};
+use sha2::Sha256;
+
+use crate::ConnId;
+
+/// Generate a random hex encoded secret, for when none is configured.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    thread_rng().fill_bytes(&mut secret);
+    secret.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn mac(secret: &str, claims: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(claims.as_bytes());
+    mac
+}
+
+/// Issue a token for the user's connection `conn`.
+pub fn issue(secret: &str, conn: ConnId, user_id: &str) -> String {
+    let claims = format!("{conn}.{user_id}.{}", now());
+    let signature = mac(secret, &claims).finalize().into_bytes();
+
+    format!("{claims}.{signature:x}")
+}
+
+/// Check a token issued to `user_id` at most `ttl_secs` ago, returning the connection it was
+/// issued to.
+pub fn verify(secret: &str, token: &str, user_id: &str, ttl_secs: u64) -> Option<ConnId> {
+    let (claims, signature) = token.rsplit_once('.')?;
+    mac(secret, claims)
+        .verify_slice(&decode_hex(signature)?)
+        .ok()?;
+
+    let mut parts = claims.splitn(3, '.');
+    let conn = parts.next()?.parse().ok()?;
+    let issued_to = parts.next()?;
+    let issued_at: u64 = parts.next()?.parse().ok()?;
+
+    (issued_to == user_id && now().saturating_sub(issued_at) <= ttl_secs).then_some(conn)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    /// A token for `user_id` signed as if it had been issued at `issued_at`.
+    fn token_at(secret: &str, conn: ConnId, user_id: &str, issued_at: u64) -> String {
+        let claims = format!("{conn}.{user_id}.{issued_at}");
+        let signature = mac(secret, &claims).finalize().into_bytes();
+
+        format!("{claims}.{signature:x}")
+    }
+
+    #[test]
+    fn verifies_its_own_tokens() {
+        let token = issue(SECRET, 42, "user-1");
+
+        assert_eq!(verify(SECRET, &token, "user-1", 60), Some(42));
+    }
+
+    #[test]
+    fn rejects_other_users_and_secrets() {
+        let token = issue(SECRET, 42, "user-1");
+
+        assert_eq!(verify(SECRET, &token, "user-2", 60), None);
+        assert_eq!(verify("other", &token, "user-1", 60), None);
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let token = issue(SECRET, 42, "user-1");
+        let forged = token.replacen("42.", "43.", 1);
+
+        assert_eq!(verify(SECRET, &forged, "user-1", 60), None);
+        assert_eq!(verify(SECRET, "42.user-1.0.zz", "user-1", 60), None);
+        assert_eq!(verify(SECRET, "garbage", "user-1", 60), None);
+    }
+
+    #[test]
+    fn expires_after_the_ttl() {
+        let fresh = token_at(SECRET, 7, "user-1", now() - 30);
+        let stale = token_at(SECRET, 7, "user-1", now() - 120);
+
+        assert_eq!(verify(SECRET, &fresh, "user-1", 60), Some(7));
+        assert_eq!(verify(SECRET, &stale, "user-1", 60), None);
+    }
+}
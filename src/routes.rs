@@ -1,27 +1,15 @@
 use crate::db;
-use crate::models;
-use crate::server;
+use crate::error::ApiError;
 // use crate::session;
 use crate::types::DbPool;
-use actix::*;
 use actix_files::NamedFile;
-use actix_session::Session;
-use actix_web::error::ErrorInternalServerError;
-use actix_web::{get, post, web, Error, HttpRequest, HttpResponse, Responder, Scope};
-use bcrypt::verify;
-use diesel::result::DatabaseErrorKind;
-use diesel::{
-    prelude::*,
-    r2d2::{self, ConnectionManager},
-};
-use serde::Deserialize;
-use serde_json::json;
-
-use std::time::Instant;
+use actix_web::{get, web, HttpResponse, Scope};
 use uuid::Uuid;
 
-pub async fn index() -> impl Responder {
-    NamedFile::open_async("./static/index.html").await.unwrap()
+pub async fn index() -> Result<NamedFile, ApiError> {
+    NamedFile::open_async("./static/index.html")
+        .await
+        .map_err(|err| ApiError::Internal(err.to_string()))
 }
 
 // pub async fn chat_server(
@@ -48,31 +36,28 @@ pub async fn index() -> impl Responder {
 pub async fn get_conversation_by_id(
     pool: web::Data<DbPool>,
     uid: web::Path<Uuid>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
     let room_id = uid.to_owned();
     let conversations = web::block(move || {
         let mut conn = pool.get()?;
         db::get_conversation_by_room_uid(&mut conn, room_id)
     })
-    .await?
-    .map_err(ErrorInternalServerError)?;
-    if let Some(data) = conversations {
-        Ok(HttpResponse::Ok().json(data))
-    } else {
-        let res = HttpResponse::NotFound().body(
-            json!({
-                "error": 404,
-                "message": format!("No conversation with room_id: {room_id}")
-            })
-            .to_string(),
-        );
-        Ok(res)
+    .await??;
+    match conversations {
+        Some(data) => Ok(HttpResponse::Ok().json(data)),
+        None => Err(ApiError::NotFound(format!(
+            "No conversation with room_id: {room_id}"
+        ))),
     }
 }
 
+pub mod admin;
 pub mod auth;
 pub mod conversations;
+pub mod filters;
+pub mod reports;
 pub mod rooms;
+pub mod two_factor;
 pub mod users;
 pub mod ws;
 
@@ -81,7 +66,16 @@ pub fn create_auth_scope() -> Scope {
         .service(auth::sign_up)
         .service(auth::sign_in)
         .service(auth::get_current_user)
+        .service(auth::update_profile)
+        .service(auth::delete_account)
         .service(auth::log_out)
+        .service(auth::change_password)
+        .service(auth::request_password_reset)
+        .service(auth::reset_password)
+        .service(two_factor::verify_sign_in)
+        .service(two_factor::enroll)
+        .service(two_factor::confirm)
+        .service(two_factor::disable)
 }
 
 pub fn create_room_scope() -> Scope {
@@ -89,9 +83,46 @@ pub fn create_room_scope() -> Scope {
         .service(rooms::get_rooms)
         .service(rooms::create_room)
         .service(rooms::delete_room)
+        .service(rooms::unarchive_room)
         .service(rooms::join_room)
         .service(rooms::exit_room)
         .service(rooms::get_room)
+        .service(rooms::update_room)
+        .service(rooms::add_moderator)
+        .service(rooms::remove_moderator)
+        .service(rooms::get_room_audit_events)
+        .service(filters::get_filter_decisions)
+        .service(filters::get_room_filters)
+        .service(filters::create_room_filter)
+        .service(filters::delete_room_filter)
+        .service(reports::create_report)
+        .service(reports::get_reports)
+        .service(reports::resolve_report)
+        .service(reports::unban_user)
+}
+
+pub fn create_admin_scope() -> Scope {
+    web::scope("/admin")
+        .service(admin::get_audit_events)
+        .service(admin::get_users)
+        .service(admin::suspend_user)
+        .service(admin::unsuspend_user)
+        .service(admin::grant_admin)
+        .service(admin::revoke_admin)
+        .service(admin::disconnect_user)
+        .service(admin::delete_room)
+        .service(admin::get_connections)
+        .service(admin::disconnect_connection)
+        .service(admin::get_stats)
+}
+
+pub fn create_user_scope() -> Scope {
+    // the block routes go first so `/blocks` isn't taken for a user id
+    web::scope("/users")
+        .service(users::get_blocks)
+        .service(users::block_user)
+        .service(users::unblock_user)
+        .service(users::get_user_by_id)
 }
 
 pub fn create_conversation_scope() -> Scope {
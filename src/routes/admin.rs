@@ -0,0 +1,439 @@
+use actix_session::Session;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use diesel::{Connection, SqliteConnection};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    db::{self, audit::AuditQuery},
+    error::ApiError,
+    filters::FilterCache,
+    models::{AdminUserResponse, User},
+    server::ChatServerHandle,
+    types::DbPool,
+    utils::get_user_id,
+    ConnId,
+};
+
+/// Users returned when no limit is given.
+const DEFAULT_USER_LIMIT: i64 = 50;
+
+/// Most users returned by one request.
+const MAX_USER_LIMIT: i64 = 200;
+
+/// Load the signed in user, failing unless they are a site admin.
+pub async fn require_admin(pool: &web::Data<DbPool>, session: &Session) -> Result<User, ApiError> {
+    let user_id = get_user_id(session)?;
+    let pool = pool.clone();
+
+    let user = web::block(move || {
+        let mut conn = pool.get()?;
+        db::users::find_user_by_uid(&mut conn, user_id)
+    })
+    .await??;
+
+    match user {
+        Some(user) if user.is_admin => Ok(user),
+        _ => Err(ApiError::Forbidden("Site admins only.".to_string())),
+    }
+}
+
+/// The audit log across all rooms and accounts.
+#[get("/audit")]
+pub async fn get_audit_events(
+    pool: web::Data<DbPool>,
+    session: Session,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    require_admin(&pool, &session).await?;
+
+    let events = web::block(move || {
+        let mut conn = pool.get()?;
+        db::audit::list_events(&mut conn, &query)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(events))
+}
+
+#[derive(Deserialize)]
+struct ListUsersQuery {
+    /// Only users whose username contains this.
+    search: Option<String>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[get("/users")]
+pub async fn get_users(
+    pool: web::Data<DbPool>,
+    session: Session,
+    query: web::Query<ListUsersQuery>,
+) -> Result<HttpResponse, ApiError> {
+    require_admin(&pool, &session).await?;
+
+    let users = web::block(move || {
+        let mut conn = pool.get()?;
+        db::users::list_users(
+            &mut conn,
+            query.search.as_deref(),
+            query.offset.unwrap_or(0).max(0),
+            query
+                .limit
+                .unwrap_or(DEFAULT_USER_LIMIT)
+                .clamp(1, MAX_USER_LIMIT),
+        )
+    })
+    .await??;
+
+    let users: Vec<AdminUserResponse> = users.into_iter().map(Into::into).collect();
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Run `change` on the target user and record `action` in the audit log, in one transaction.
+///
+/// Fails with 404 if there is no such user.
+async fn update_user(
+    pool: web::Data<DbPool>,
+    admin: User,
+    user_id: Uuid,
+    action: &'static str,
+    details: Value,
+    change: impl FnOnce(&mut SqliteConnection) -> Result<Option<User>, db::DbError> + Send + 'static,
+) -> Result<User, ApiError> {
+    let user = web::block(move || {
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let Some(user) = change(conn)? else {
+                return Ok(None);
+            };
+
+            db::audit::record(conn, Some(&admin.id), action, None, Some(&user.id), details)?;
+
+            Ok::<_, db::DbError>(Some(user))
+        })
+    })
+    .await??;
+
+    match user {
+        Some(user) => Ok(user),
+        None => Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        ))),
+    }
+}
+
+#[derive(Deserialize)]
+struct SuspendData {
+    reason: Option<String>,
+}
+
+/// Suspend an account: it can't sign in or use its sessions, and its sockets are closed.
+#[post("/users/{user_id}/suspend")]
+pub async fn suspend_user(
+    pool: web::Data<DbPool>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+    data: web::Json<SuspendData>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_admin(&pool, &session).await?;
+    let user_id = user_id.into_inner();
+
+    if admin.id == user_id.to_string() {
+        return Err(ApiError::Unprocessable(
+            "You can't suspend yourself.".to_string(),
+        ));
+    }
+
+    let reason = data
+        .0
+        .reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty())
+        .unwrap_or_else(|| "Suspended by an administrator.".to_string());
+
+    let user = update_user(
+        pool,
+        admin,
+        user_id,
+        "admin.suspend",
+        json!({ "reason": reason }),
+        move |conn| db::users::set_suspended(conn, user_id, Some(&reason)),
+    )
+    .await?;
+
+    chat_server.disconnect_user(user.id.clone()).await;
+
+    Ok(HttpResponse::Ok().json(AdminUserResponse::from(user)))
+}
+
+#[post("/users/{user_id}/unsuspend")]
+pub async fn unsuspend_user(
+    pool: web::Data<DbPool>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_admin(&pool, &session).await?;
+    let user_id = user_id.into_inner();
+
+    let user = update_user(
+        pool,
+        admin,
+        user_id,
+        "admin.unsuspend",
+        json!({}),
+        move |conn| db::users::set_suspended(conn, user_id, None),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(AdminUserResponse::from(user)))
+}
+
+#[put("/users/{user_id}/admin")]
+pub async fn grant_admin(
+    pool: web::Data<DbPool>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    set_admin(pool, session, user_id.into_inner(), true).await
+}
+
+#[delete("/users/{user_id}/admin")]
+pub async fn revoke_admin(
+    pool: web::Data<DbPool>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    set_admin(pool, session, user_id.into_inner(), false).await
+}
+
+async fn set_admin(
+    pool: web::Data<DbPool>,
+    session: Session,
+    user_id: Uuid,
+    is_admin: bool,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_admin(&pool, &session).await?;
+
+    // keeps the site from being left without an admin by accident
+    if !is_admin && admin.id == user_id.to_string() {
+        return Err(ApiError::Unprocessable(
+            "You can't revoke your own admin role.".to_string(),
+        ));
+    }
+
+    let action = if is_admin {
+        "admin.grant_admin"
+    } else {
+        "admin.revoke_admin"
+    };
+
+    let user = update_user(pool, admin, user_id, action, json!({}), move |conn| {
+        db::users::set_admin(conn, user_id, is_admin)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(AdminUserResponse::from(user)))
+}
+
+enum Deletion {
+    Deleted,
+    RoomNotFound,
+    NotArchived,
+    /// Still in its grace period, which ends at the given time.
+    TooEarly(DateTime<Utc>),
+}
+
+/// Delete a room for good, with its messages and members, whoever owns it.
+///
+/// Only rooms archived for longer than the grace period can be deleted.
+#[delete("/rooms/{room_id}")]
+pub async fn delete_room(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+    chat_server: web::Data<ChatServerHandle>,
+    config: web::Data<Config>,
+    filters: web::Data<FilterCache>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_admin(&pool, &session).await?;
+    let room_id = room_id.into_inner();
+    let grace_period = Duration::days(config.admin.room_delete_grace_days);
+
+    let deletion = web::block(move || {
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let Some(room) = db::rooms::find_room(conn, &room_id.to_string())? else {
+                return Ok(Deletion::RoomNotFound);
+            };
+            let Some(archived_at) = &room.archived_at else {
+                return Ok(Deletion::NotArchived);
+            };
+
+            let deletable_at = DateTime::parse_from_rfc3339(archived_at)?.to_utc() + grace_period;
+            if Utc::now() < deletable_at {
+                return Ok(Deletion::TooEarly(deletable_at));
+            }
+
+            db::rooms::delete_room(conn, room_id)?;
+            db::audit::record(
+                conn,
+                Some(&admin.id),
+                "admin.delete_room",
+                Some(&room.id),
+                Some(&room.owner_id),
+                json!({ "name": room.name }),
+            )?;
+
+            Ok::<_, db::DbError>(Deletion::Deleted)
+        })
+    })
+    .await??;
+
+    match deletion {
+        Deletion::Deleted => {}
+        Deletion::RoomNotFound => {
+            return Err(ApiError::NotFound(format!(
+                "Room {} is not found.",
+                room_id
+            )));
+        }
+        Deletion::NotArchived => {
+            return Err(ApiError::Conflict(
+                "Archive the room before deleting it.".to_string(),
+            ));
+        }
+        Deletion::TooEarly(deletable_at) => {
+            return Err(
+                ApiError::Conflict("The room is still in its grace period.".to_string())
+                    .with_details(json!({ "deletable_at": deletable_at.to_rfc3339() })),
+            );
+        }
+    }
+
+    filters.forget(&room_id.to_string());
+
+    chat_server
+        .close_room(
+            room_id.to_string(),
+            json!({
+                "type": "delete_room",
+                "data": {
+                    "room_id": room_id.to_string(),
+                }
+            })
+            .to_string(),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Open WebSocket connections and the rooms they receive messages for.
+#[get("/connections")]
+pub async fn get_connections(
+    pool: web::Data<DbPool>,
+    session: Session,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    require_admin(&pool, &session).await?;
+
+    Ok(HttpResponse::Ok().json(chat_server.list_connections().await))
+}
+
+/// Close a single WebSocket connection.
+#[delete("/connections/{conn_id}")]
+pub async fn disconnect_connection(
+    pool: web::Data<DbPool>,
+    session: Session,
+    conn_id: web::Path<ConnId>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_admin(&pool, &session).await?;
+    let conn_id = conn_id.into_inner();
+
+    let Some(connection) = chat_server
+        .list_connections()
+        .await
+        .into_iter()
+        .find(|connection| connection.conn_id == conn_id.to_string())
+    else {
+        return Err(ApiError::NotFound(format!(
+            "Connection {} is not open.",
+            conn_id
+        )));
+    };
+
+    chat_server.disconnect(conn_id).await;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        db::audit::record(
+            &mut conn,
+            Some(&admin.id),
+            "admin.disconnect",
+            None,
+            Some(&connection.user_id),
+            json!({ "conn_id": conn_id.to_string() }),
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Close every WebSocket connection of a user.
+#[delete("/users/{user_id}/connections")]
+pub async fn disconnect_user(
+    pool: web::Data<DbPool>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_admin(&pool, &session).await?;
+    let user_id = user_id.to_string();
+
+    chat_server.disconnect_user(user_id.clone()).await;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        db::audit::record(
+            &mut conn,
+            Some(&admin.id),
+            "admin.disconnect",
+            None,
+            Some(&user_id),
+            json!({}),
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Site totals from the database and live numbers from the chat server.
+#[get("/stats")]
+pub async fn get_stats(
+    pool: web::Data<DbPool>,
+    session: Session,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    require_admin(&pool, &session).await?;
+
+    let counts = web::block(move || {
+        let mut conn = pool.get()?;
+        db::stats::site_counts(&mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "database": counts,
+        "live": chat_server.stats().await,
+    })))
+}
@@ -1,14 +1,22 @@
 use crate::{
+    config::Config,
     db,
-    models::{self, User},
+    error::ApiError,
+    login_guard::LoginGuard,
+    models::{self, MessagePolicy, ProfileChanges, RoomPolicy, User},
+    password::{self, ResetTokenDelivery},
+    rate_limit::RateLimiter,
+    routes::two_factor::PendingTwoFactor,
+    server::ChatServerHandle,
     types::DbPool,
+    utils::{get_client_ip, get_user_id},
 };
 use actix_session::Session;
-use actix_web::{body::BoxBody, get, post, web, Error, HttpRequest, HttpResponse};
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
 use bcrypt::verify;
-use diesel::result::DatabaseErrorKind;
 use serde::Deserialize;
 use serde_json::json;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Deserialize)]
@@ -19,40 +27,39 @@ struct SignData {
 
 #[post["/signup"]]
 pub async fn sign_up(
+    request: HttpRequest,
     pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    rate_limiter: web::Data<RateLimiter>,
     form: web::Json<models::NewUser>,
     session: Session,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
+    rate_limiter.check_http(
+        &format!("auth:ip:{}", get_client_ip(&request)),
+        config.rate_limit.auth_per_ip,
+    )?;
+
     let signin: bool = form.sign_in;
     let username = form.username.clone();
+    let cost = config.password.bcrypt_cost;
+
+    password::validate_password(&config.password, &form.username, &form.password)
+        .map_err(ApiError::Unprocessable)?;
+
     let user = web::block(move || {
         let mut conn = pool.get()?;
-        db::users::insert_new_user(&mut conn, &form.username, &form.password)
+        db::users::insert_new_user(&mut conn, &form.username, &form.password, cost)
     })
     .await?
-    .map_err(|err| {
-        let error_msg = if let Some(diesel_error) = err.downcast_ref::<diesel::result::Error>() {
-            match diesel_error {
-                diesel::result::Error::DatabaseError(kind, _) => match kind {
-                    DatabaseErrorKind::UniqueViolation => {
-                        format!("Username {} already exists.", username)
-                    }
-                    _ => diesel_error.to_string(),
-                },
-                _ => diesel_error.to_string(),
-            }
-        } else {
-            err.to_string()
-        };
-
-        actix_web::error::ErrorUnprocessableEntity(json!({
-            "success": false,
-            "message": error_msg
-        }))
+    .map_err(|err| match ApiError::from(err) {
+        err if err.code() == "already_exists" => {
+            ApiError::Conflict(format!("Username {} already exists.", username))
+        }
+        err => err,
     })?;
 
     if signin {
-        session.insert("user_id", user.id).unwrap();
+        session.insert("user_id", user.id)?;
     }
 
     Ok(HttpResponse::Ok().json(json!({
@@ -62,57 +69,160 @@ pub async fn sign_up(
 
 #[post("/signin")]
 pub async fn sign_in(
+    request: HttpRequest,
     pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    login_guard: web::Data<LoginGuard>,
+    rate_limiter: web::Data<RateLimiter>,
     session: Session,
     signin_data: web::Json<SignData>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
     let SignData { username, password } = signin_data.0;
+    let ip = get_client_ip(&request);
+
+    rate_limiter.check_http(&format!("auth:ip:{ip}"), config.rate_limit.auth_per_ip)?;
+
+    if let Err(retry_after) = login_guard.begin_attempt(&username, &ip) {
+        record_failed_login(pool, username, None, ip, "throttled").await;
+
+        return Err(too_many_failed_sign_ins(retry_after));
+    }
 
     let username_clone = username.clone();
+    let password_clone = password.clone();
+    let cost = config.password.bcrypt_cost;
 
-    let user = web::block(move || {
+    let (user, verified) = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            let user = db::users::find_user_by_username(&mut conn, username_clone)?;
+
+            let verified = match &user {
+                Some(user) => verify(&password_clone, &user.password).unwrap_or(false),
+                None => {
+                    // keep the response time of unknown usernames in line with wrong passwords
+                    password::dummy_verify(&password_clone, cost);
+                    false
+                }
+            };
+
+            Ok::<_, db::DbError>((user, verified))
+        })
+        .await??
+    };
+
+    let user = match user {
+        Some(user) if verified => user,
+        user => {
+            let (user_id, reason) = match user {
+                Some(user) => (Some(user.id), "wrong_password"),
+                None => (None, "unknown_user"),
+            };
+            record_failed_login(pool, username, user_id, ip, reason).await;
+
+            return Err(ApiError::Unauthorized(
+                "Invalid username or password.".to_string(),
+            ));
+        }
+    };
+    login_guard.refund(&username, &ip);
+
+    // transparently upgrade hashes created with a different cost
+    if password::needs_rehash(&user.password, cost) {
+        let user_id = Uuid::parse_str(&user.id)?;
+        let res = web::block(move || {
+            let mut conn = pool.get()?;
+            let hashed = password::hash_password(&password, cost)?;
+            db::users::update_password(&mut conn, user_id, &hashed)
+        })
+        .await
+        .map_err(db::DbError::from)
+        .and_then(|res| res);
+
+        if let Err(err) = res {
+            log::warn!("failed to rehash password for user {user_id}: {err}");
+        }
+    }
+
+    check_suspended(&user)?;
+
+    if user.totp_enabled {
+        // the password was right but the session stays unauthenticated until the second
+        // factor is verified in `two_factor::verify_sign_in`
+        session.remove("user_id");
+        session.insert(
+            "pending_2fa",
+            PendingTwoFactor::new(Uuid::parse_str(&user.id)?),
+        )?;
+
+        return Ok(HttpResponse::Ok().json(json!({
+            "two_factor_required": true
+        })));
+    }
+
+    login_guard.record_success(&username);
+
+    session.remove("pending_2fa");
+    session.insert("user_id", user.id.clone())?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Fails if the account is suspended.
+pub fn check_suspended(user: &User) -> Result<(), ApiError> {
+    if user.suspended_at.is_none() {
+        return Ok(());
+    }
+
+    Err(
+        ApiError::Forbidden("Your account is suspended.".to_string())
+            .with_details(json!({ "reason": user.suspended_reason })),
+    )
+}
+
+/// The error for a sign in refused by the [`LoginGuard`] backoff.
+pub fn too_many_failed_sign_ins(retry_after: Duration) -> ApiError {
+    let retry_after = retry_after.as_secs().max(1);
+
+    ApiError::RateLimited {
+        message: format!("Too many failed sign in attempts. Try again in {retry_after} seconds."),
+        retry_after,
+    }
+}
+
+/// Write an audit record for a failed sign in. Failures to record are only logged.
+pub(crate) async fn record_failed_login(
+    pool: web::Data<DbPool>,
+    username: String,
+    user_id: Option<String>,
+    ip: String,
+    reason: &'static str,
+) {
+    log::warn!("failed sign in for {username:?} from {ip}: {reason}");
+
+    let res = web::block(move || {
         let mut conn = pool.get()?;
-        db::users::find_user_by_username(&mut conn, username_clone)
+        db::failed_logins::record_failed_login(&mut conn, username, user_id, ip, reason)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
-
-    if let Some(user) = user {
-        if verify(password, &user.password).unwrap() {
-            session.insert("user_id", user.id.clone()).unwrap();
-            Ok(HttpResponse::Ok().json(user))
-        } else {
-            let res = HttpResponse::Unauthorized().body(
-                json!({
-                    "message": format!("Wrong password for username: {}", &username)
-                })
-                .to_string(),
-            );
-            Ok(res)
-        }
-    } else {
-        let res = HttpResponse::NotFound().body(
-            json!({
-                "message": format!("No user found with username: {}", &username)
-            })
-            .to_string(),
-        );
-        Ok(res)
+    .await
+    .map_err(db::DbError::from)
+    .and_then(|res| res);
+
+    if let Err(err) = res {
+        log::error!("failed to record failed sign in: {err}");
     }
 }
 
 #[post("/logout")]
-pub async fn log_out(session: Session) -> HttpResponse {
+pub async fn log_out(session: Session) -> Result<HttpResponse, ApiError> {
     let user_id: Option<Uuid> = session.get("user_id").unwrap_or(None);
 
     match user_id {
         Some(_) => {
             session.purge();
-            HttpResponse::Ok().json(json!({}))
+            Ok(HttpResponse::Ok().json(json!({})))
         }
-        None => HttpResponse::Unauthorized().json(json!({
-            "message": "You're not signed in."
-        })),
+        None => Err(ApiError::Unauthorized("You're not signed in.".to_string())),
     }
 }
 
@@ -120,21 +230,427 @@ pub async fn log_out(session: Session) -> HttpResponse {
 pub async fn get_current_user(
     pool: web::Data<DbPool>,
     session: Session,
-) -> Result<HttpResponse, Error> {
-    let user_id = session.get::<Uuid>("user_id").unwrap().unwrap();
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
 
     let user = web::block(move || {
         let mut conn = pool.get()?;
         db::users::find_user_by_uid(&mut conn, user_id)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
     if user.is_none() {
-        Ok(HttpResponse::NotFound().json(json!({
-            "message": format!("User {} does not exist.", user_id),
-        })))
+        Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        )))
     } else {
         Ok(HttpResponse::Ok().json(user))
     }
 }
+
+/// Check the password a signed in user confirms a sensitive change with.
+///
+/// Wrong passwords count against the account and IP like failed sign ins, so a stolen session
+/// can't be used to guess it. bcrypt runs on the blocking thread pool, as it is slow on purpose.
+pub(crate) async fn check_current_password(
+    request: &HttpRequest,
+    login_guard: &LoginGuard,
+    user: &User,
+    password: String,
+) -> Result<(), ApiError> {
+    let ip = get_client_ip(request);
+
+    if let Err(retry_after) = login_guard.begin_attempt(&user.username, &ip) {
+        return Err(too_many_failed_sign_ins(retry_after));
+    }
+
+    let hashed = user.password.clone();
+    if !web::block(move || verify(&password, &hashed).unwrap_or(false)).await? {
+        return Err(ApiError::Unauthorized("Wrong password.".to_string()));
+    }
+
+    login_guard.refund(&user.username, &ip);
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordData {
+    old_password: String,
+    new_password: String,
+}
+
+#[post("/user/password")]
+pub async fn change_password(
+    request: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    login_guard: web::Data<LoginGuard>,
+    session: Session,
+    data: web::Json<ChangePasswordData>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+    let ChangePasswordData {
+        old_password,
+        new_password,
+    } = data.0;
+
+    let user = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            db::users::find_user_by_uid(&mut conn, user_id)
+        })
+        .await??
+    };
+
+    let Some(user) = user else {
+        return Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        )));
+    };
+
+    check_current_password(&request, &login_guard, &user, old_password).await?;
+
+    if let Err(message) =
+        password::validate_password(&config.password, &user.username, &new_password)
+    {
+        return Err(ApiError::Unprocessable(message));
+    }
+
+    let cost = config.password.bcrypt_cost;
+    web::block(move || {
+        let mut conn = pool.get()?;
+        let hashed = password::hash_password(&new_password, cost)?;
+        db::users::update_password(&mut conn, user_id, &hashed)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+#[derive(Deserialize)]
+struct RequestResetData {
+    username: String,
+}
+
+/// Start a password reset. Always succeeds so it can't be used to probe for usernames.
+#[post("/password/reset")]
+pub async fn request_password_reset(
+    request: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    rate_limiter: web::Data<RateLimiter>,
+    delivery: web::Data<dyn ResetTokenDelivery>,
+    data: web::Json<RequestResetData>,
+) -> Result<HttpResponse, ApiError> {
+    rate_limiter.check_http(
+        &format!("auth:ip:{}", get_client_ip(&request)),
+        config.rate_limit.auth_per_ip,
+    )?;
+
+    let username = data.0.username;
+    let ttl_minutes = config.password.reset_token_ttl_minutes;
+
+    let res = web::block(move || {
+        let mut conn = pool.get()?;
+        let Some(user) = db::users::find_user_by_username(&mut conn, username)? else {
+            return Ok(None);
+        };
+
+        let token = password::generate_reset_token();
+        db::password_resets::create_reset_token(
+            &mut conn,
+            &user.id,
+            password::hash_token(&token),
+            ttl_minutes,
+        )?;
+
+        Ok::<_, db::DbError>(Some((user, token)))
+    })
+    .await??;
+
+    if let Some((user, token)) = res {
+        delivery.deliver(&user, &token);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordData {
+    token: String,
+    new_password: String,
+}
+
+#[post("/password/reset/confirm")]
+pub async fn reset_password(
+    request: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    rate_limiter: web::Data<RateLimiter>,
+    data: web::Json<ResetPasswordData>,
+) -> Result<HttpResponse, ApiError> {
+    rate_limiter.check_http(
+        &format!("auth:ip:{}", get_client_ip(&request)),
+        config.rate_limit.auth_per_ip,
+    )?;
+
+    let ResetPasswordData {
+        token,
+        new_password,
+    } = data.0;
+
+    let res = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            let Some(token) = db::password_resets::find_valid_reset_token(
+                &mut conn,
+                &password::hash_token(&token),
+            )?
+            else {
+                return Ok(None);
+            };
+            let user = db::users::find_user_by_uid(&mut conn, Uuid::parse_str(&token.user_id)?)?;
+
+            Ok::<_, db::DbError>(user.map(|user| (token, user)))
+        })
+        .await??
+    };
+
+    let Some((token, user)) = res else {
+        return Err(ApiError::BadRequest(
+            "Invalid or expired reset token.".to_string(),
+        ));
+    };
+
+    if let Err(message) =
+        password::validate_password(&config.password, &user.username, &new_password)
+    {
+        return Err(ApiError::Unprocessable(message));
+    }
+
+    let cost = config.password.bcrypt_cost;
+    let reset = web::block(move || {
+        let mut conn = pool.get()?;
+        let hashed = password::hash_password(&new_password, cost)?;
+        db::password_resets::reset_password(&mut conn, &token, &hashed)
+    })
+    .await??;
+
+    if !reset {
+        return Err(ApiError::BadRequest(
+            "Invalid or expired reset token.".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+#[derive(Deserialize)]
+struct UpdateProfileData {
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+    bio: Option<String>,
+    status_message: Option<String>,
+}
+
+/// Turn a submitted profile field into a change. Empty strings clear the field.
+fn profile_field(
+    name: &str,
+    value: Option<String>,
+    max_len: usize,
+) -> Result<Option<Option<String>>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(Some(None));
+    }
+
+    if value.chars().count() > max_len {
+        return Err(format!("{name} must be at most {max_len} characters long."));
+    }
+
+    Ok(Some(Some(value.to_string())))
+}
+
+impl TryFrom<UpdateProfileData> for ProfileChanges {
+    type Error = String;
+
+    fn try_from(data: UpdateProfileData) -> Result<Self, Self::Error> {
+        let changes = ProfileChanges {
+            display_name: profile_field("Display name", data.display_name, 50)?,
+            avatar_url: profile_field("Avatar URL", data.avatar_url, 500)?,
+            bio: profile_field("Bio", data.bio, 500)?,
+            status_message: profile_field("Status message", data.status_message, 100)?,
+        };
+
+        if let Some(Some(url)) = &changes.avatar_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err("Avatar URL must be an http(s) URL.".to_string());
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[patch("/user")]
+pub async fn update_profile(
+    pool: web::Data<DbPool>,
+    session: Session,
+    data: web::Json<UpdateProfileData>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+
+    let changes = match ProfileChanges::try_from(data.0) {
+        Ok(changes) => changes,
+        Err(message) => {
+            return Err(ApiError::Unprocessable(message));
+        }
+    };
+
+    let (user, rooms) = web::block(move || {
+        let mut conn = pool.get()?;
+
+        let user = match changes {
+            ProfileChanges {
+                display_name: None,
+                avatar_url: None,
+                bio: None,
+                status_message: None,
+            } => db::users::find_user_by_uid(&mut conn, user_id)?,
+            changes => Some(db::users::update_profile(&mut conn, user_id, &changes)?),
+        };
+        let rooms = db::rooms::get_user_joined_rooms(&mut conn, user_id.to_string())?;
+
+        Ok::<_, db::DbError>((user, rooms))
+    })
+    .await??;
+
+    let Some(user) = user else {
+        return Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        )));
+    };
+
+    chat_server
+        .send_rooms_message(
+            rooms.into_iter().map(|room| room.id).collect(),
+            json!({
+                "type": "profile_updated",
+                "data": user,
+            })
+            .to_string(),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Deserialize)]
+struct DeleteAccountData {
+    password: String,
+    #[serde(default)]
+    room_policy: RoomPolicy,
+    #[serde(default)]
+    message_policy: MessagePolicy,
+}
+
+#[delete("/user")]
+pub async fn delete_account(
+    request: HttpRequest,
+    pool: web::Data<DbPool>,
+    login_guard: web::Data<LoginGuard>,
+    session: Session,
+    data: web::Json<DeleteAccountData>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+    let DeleteAccountData {
+        password,
+        room_policy,
+        message_policy,
+    } = data.0;
+
+    let user = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            db::users::find_user_by_uid(&mut conn, user_id)
+        })
+        .await??
+    };
+
+    let Some(user) = user else {
+        return Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        )));
+    };
+
+    check_current_password(&request, &login_guard, &user, password).await?;
+
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        db::users::delete_account(&mut conn, user_id, room_policy, message_policy)
+    })
+    .await??;
+
+    session.purge();
+    chat_server.disconnect_user(user_id.to_string()).await;
+
+    for room in deleted.archived_rooms {
+        chat_server
+            .announce(
+                room.id.clone(),
+                json!({
+                    "type": "archive_room",
+                    "data": {
+                        "room": room,
+                    }
+                })
+                .to_string(),
+            )
+            .await;
+    }
+
+    for room in deleted.transferred_rooms {
+        chat_server
+            .announce(
+                room.id.clone(),
+                json!({
+                    "type": "room_updated",
+                    "data": room,
+                })
+                .to_string(),
+            )
+            .await;
+    }
+
+    for room_id in deleted.left_rooms {
+        chat_server
+            .announce(
+                room_id.clone(),
+                json!({
+                    "type": "exit_room",
+                    "data": {
+                        "room_id": room_id,
+                        "user_id": user_id.to_string(),
+                    }
+                })
+                .to_string(),
+            )
+            .await;
+    }
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
@@ -1,64 +1,197 @@
 use actix_session::Session;
-use actix_web::{
-    error::{Error, ErrorInternalServerError},
-    post, web, HttpRequest, HttpResponse,
-};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use diesel::SqliteConnection;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use uuid::Uuid;
 
 use crate::{
+    config::Config,
     db,
+    error::ApiError,
+    filters::{FilterCache, FilterKind},
+    models::{Conversation, Room},
+    rate_limit::RateLimiter,
     server::ChatServerHandle,
     types::DbPool,
     utils::{get_conn_id, get_user_id},
-    ConnId,
 };
 
+use super::rooms::{archived_error, authorize, Access};
+
 #[derive(Debug, Serialize, Deserialize)]
 struct CreateConversation {
     message: String,
-    room_id: String,
+    room_id: Uuid,
+}
+
+enum Posted {
+    Conversation(Conversation),
+    Banned,
+    Archived(String),
+    SlowMode {
+        retry_after: i64,
+        slow_mode_seconds: i32,
+    },
+    Rejected(FilterKind),
+}
+
+/// Seconds the user still has to wait before posting in a room with slow mode on.
+///
+/// The owner and moderators are exempt.
+fn slow_mode_cooldown(
+    conn: &mut SqliteConnection,
+    room: &Room,
+    user_id: &str,
+) -> Result<Option<i64>, db::DbError> {
+    if room.slow_mode_seconds <= 0 || db::rooms_users::is_moderator(conn, room, user_id)? {
+        return Ok(None);
+    }
+
+    let Some(last_message_at) = db::conversations::last_message_at(conn, &room.id, user_id)? else {
+        return Ok(None);
+    };
+
+    let elapsed_ms =
+        (Utc::now() - DateTime::parse_from_rfc3339(&last_message_at)?.to_utc()).num_milliseconds();
+    let remaining_ms = room.slow_mode_seconds as i64 * 1000 - elapsed_ms;
+
+    // round up so clients never retry a moment too early
+    Ok((remaining_ms > 0).then(|| (remaining_ms + 999) / 1000))
 }
 
 #[post("")]
+#[allow(clippy::too_many_arguments)]
 pub async fn create_conversation(
     request: HttpRequest,
     pool: web::Data<DbPool>,
     form_data: web::Json<CreateConversation>,
     session: Session,
     chat_server: web::Data<ChatServerHandle>,
-) -> Result<HttpResponse, Error> {
+    config: web::Data<Config>,
+    rate_limiter: web::Data<RateLimiter>,
+    filters: web::Data<FilterCache>,
+) -> Result<HttpResponse, ApiError> {
     println!("enter create conversation");
     println!("{:?}", session.entries());
-    let user_id = get_user_id(&session);
+    let user_id = get_user_id(&session)?;
+
+    // the header is the client's word; only trust it for the user's own open connections
     let conn_id = get_conn_id(&request)?;
+    let conn_id = match chat_server.connection_user(conn_id).await {
+        Some(owner) if owner == user_id.to_string() => conn_id,
+        _ => 0,
+    };
+
+    rate_limiter.check_http(
+        &format!("message:user:{user_id}"),
+        config.rate_limit.messages_per_user,
+    )?;
+    // requests without a connection share one bucket per user
+    rate_limiter.check_http(
+        &format!("message:conn:{user_id}:{conn_id}"),
+        config.rate_limit.messages_per_connection,
+    )?;
 
     let CreateConversation { message, room_id } = form_data.0;
+    let room = authorize(&pool, &session, room_id, Access::Member).await?;
 
     let res = {
         let message = message.clone();
-        let room_id = room_id.clone();
+        let room_id = room.id.clone();
         web::block(move || {
             let mut conn = pool.get()?;
+            let user_id = user_id.to_string();
+
+            if db::rooms_users::is_banned(&mut conn, &user_id, &room_id)? {
+                return Ok(Posted::Banned);
+            }
+
+            if let Some(archived_at) = room.archived_at {
+                return Ok(Posted::Archived(archived_at));
+            }
+
+            // the cooldown check and the insert share a write lock, so parallel posts can't both
+            // pass the check
+            conn.immediate_transaction(|conn| {
+                if let Some(retry_after) = slow_mode_cooldown(conn, &room, &user_id)? {
+                    return Ok(Posted::SlowMode {
+                        retry_after,
+                        slow_mode_seconds: room.slow_mode_seconds,
+                    });
+                }
+
+                // global rules first, then the room's own
+                let rules = db::filters::list_room_filters(conn, &room_id)?
+                    .into_iter()
+                    .filter_map(|filter| Some((filter.id.clone(), filter.to_rule()?)))
+                    .collect();
+                let outcome = filters.room_chain(&room_id, rules).run(message.clone());
+
+                if let Some(kind) = outcome.rejected_by() {
+                    db::filters::record_decisions(
+                        conn,
+                        &room_id,
+                        &user_id,
+                        None,
+                        &message,
+                        &outcome.decisions,
+                    )?;
+                    return Ok(Posted::Rejected(kind));
+                }
 
-            db::conversations::create_conversation(&mut conn, message, room_id, user_id.to_string())
+                let conversation = db::conversations::create_conversation(
+                    conn,
+                    outcome.message,
+                    room_id.clone(),
+                    user_id.clone(),
+                )?;
+                db::filters::record_decisions(
+                    conn,
+                    &room_id,
+                    &user_id,
+                    Some(&conversation.id),
+                    &message,
+                    &outcome.decisions,
+                )?;
+
+                Ok::<_, db::DbError>(Posted::Conversation(conversation))
+            })
         })
-        .await?
-        .map_err(ErrorInternalServerError)?
+        .await??
+    };
+
+    let res = match res {
+        Posted::Conversation(conversation) => conversation,
+        Posted::Banned => {
+            return Err(ApiError::Forbidden(
+                "You're banned from this room.".to_string(),
+            ));
+        }
+        Posted::Archived(archived_at) => {
+            return Err(archived_error(&archived_at));
+        }
+        Posted::SlowMode {
+            retry_after,
+            slow_mode_seconds,
+        } => {
+            return Err(ApiError::RateLimited {
+                message: format!("Slow mode is on. Wait {retry_after} seconds."),
+                retry_after: retry_after as u64,
+            }
+            .with_details(json!({ "slow_mode_seconds": slow_mode_seconds })));
+        }
+        Posted::Rejected(kind) => {
+            return Err(ApiError::Unprocessable(
+                "Message was rejected by the content filter.".to_string(),
+            )
+            .with_details(json!({ "filter": kind })));
+        }
     };
 
     // send ws message
-    chat_server
-        .send_message(
-            json!({
-                "type": "message",
-                "data": res,
-            })
-            .to_string(),
-            room_id,
-            conn_id,
-        )
-        .await;
+    chat_server.send_conversation(&res, conn_id).await;
 
     Ok(HttpResponse::Ok().json(res))
 }
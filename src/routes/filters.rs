@@ -0,0 +1,132 @@
+use actix_session::Session;
+use actix_web::{delete, get, post, web, HttpResponse};
+use diesel::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{db, error::ApiError, filters::FilterRule, types::DbPool, utils::get_user_id};
+
+use super::rooms::{authorize, check_archived, Access};
+
+#[get("/{room_id}/filters")]
+pub async fn get_room_filters(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let room = authorize(&pool, &session, *room_id, Access::Moderator).await?;
+
+    let filters = web::block(move || {
+        let mut conn = pool.get()?;
+        db::filters::list_room_filters(&mut conn, &room.id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(filters))
+}
+
+#[post("/{room_id}/filters")]
+pub async fn create_room_filter(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+    rule: web::Json<FilterRule>,
+) -> Result<HttpResponse, ApiError> {
+    let room = authorize(&pool, &session, *room_id, Access::Owner).await?;
+    check_archived(&room)?;
+    let user_id = get_user_id(&session)?.to_string();
+
+    if let Err(err) = rule.compile() {
+        return Err(ApiError::Unprocessable(err));
+    }
+
+    let filter = web::block(move || {
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let filter = db::filters::create_room_filter(conn, &room.id, &rule)?;
+            db::audit::record(
+                conn,
+                Some(&user_id),
+                "room.filter_create",
+                Some(&room.id),
+                None,
+                json!({ "filter_id": filter.id, "rule": rule.0 }),
+            )?;
+
+            Ok::<_, db::DbError>(filter)
+        })
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(filter))
+}
+
+#[delete("/{room_id}/filters/{filter_id}")]
+pub async fn delete_room_filter(
+    pool: web::Data<DbPool>,
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (room_id, filter_id) = path.into_inner();
+
+    let room = authorize(&pool, &session, room_id, Access::Owner).await?;
+    check_archived(&room)?;
+    let user_id = get_user_id(&session)?.to_string();
+
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        let filter_id = filter_id.to_string();
+        conn.transaction(|conn| {
+            if !db::filters::delete_room_filter(conn, &room.id, &filter_id)? {
+                return Ok(false);
+            }
+
+            db::audit::record(
+                conn,
+                Some(&user_id),
+                "room.filter_delete",
+                Some(&room.id),
+                None,
+                json!({ "filter_id": filter_id }),
+            )?;
+
+            Ok::<_, db::DbError>(true)
+        })
+    })
+    .await??;
+
+    if !deleted {
+        return Err(ApiError::NotFound(format!(
+            "Filter {} is not found.",
+            filter_id
+        )));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+struct DecisionsQuery {
+    /// Only return decisions made before this timestamp, for paging.
+    before: Option<String>,
+}
+
+/// Messages the content filters rejected, masked or flagged in a room, newest first.
+#[get("/{room_id}/filters/decisions")]
+pub async fn get_filter_decisions(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+    query: web::Query<DecisionsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let room = authorize(&pool, &session, *room_id, Access::Moderator).await?;
+
+    let decisions = web::block(move || {
+        let mut conn = pool.get()?;
+        db::filters::list_decisions(&mut conn, &room.id, query.before.as_deref())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(decisions))
+}
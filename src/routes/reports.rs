@@ -0,0 +1,361 @@
+use actix_session::Session;
+use actix_web::{delete, get, post, web, HttpResponse};
+use diesel::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    db,
+    error::ApiError,
+    models::{Report, ReportAction, RoomUser},
+    server::ChatServerHandle,
+    types::DbPool,
+    utils::get_user_id,
+};
+
+use super::rooms::{authorize, Access};
+
+/// Longest reason a report can give, in characters.
+const MAX_REASON_LENGTH: usize = 1000;
+
+#[derive(Deserialize)]
+struct CreateReportData {
+    /// The message being reported. Its author is the reported user.
+    conversation_id: Option<String>,
+    /// The user being reported, when no particular message is.
+    user_id: Option<Uuid>,
+    reason: String,
+}
+
+enum Filed {
+    Report(Box<Report>),
+    MessageNotFound,
+    UserNotFound,
+}
+
+/// Report a message or a user of the room to its moderators.
+#[post("/{room_id}/reports")]
+pub async fn create_report(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+    data: web::Json<CreateReportData>,
+) -> Result<HttpResponse, ApiError> {
+    let room = authorize(&pool, &session, *room_id, Access::Member).await?;
+    let reporter_id = get_user_id(&session)?.to_string();
+
+    let CreateReportData {
+        conversation_id,
+        user_id,
+        reason,
+    } = data.0;
+    let reason = reason.trim().to_string();
+
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(ApiError::Unprocessable(format!(
+            "A reason of 1 to {MAX_REASON_LENGTH} characters is required."
+        )));
+    }
+
+    if conversation_id.is_none() && user_id.is_none() {
+        return Err(ApiError::Unprocessable(
+            "Either a message or a user to report is required.".to_string(),
+        ));
+    }
+
+    let filed = web::block(move || {
+        let mut conn = pool.get()?;
+
+        let (reported_user_id, conversation_id) = match (conversation_id, user_id) {
+            (Some(conversation_id), _) => {
+                let message = db::conversations::find_conversation(&mut conn, &conversation_id)?
+                    .filter(|message| message.room_id == room.id);
+
+                let Some(message) = message else {
+                    return Ok(Filed::MessageNotFound);
+                };
+
+                (message.user_id, Some(conversation_id))
+            }
+            (None, Some(user_id)) => {
+                if db::users::find_user_by_uid(&mut conn, user_id)?.is_none() {
+                    return Ok(Filed::UserNotFound);
+                }
+
+                (user_id.to_string(), None)
+            }
+            (None, None) => unreachable!("checked above"),
+        };
+
+        let report = db::reports::create_report(
+            &mut conn,
+            &room.id,
+            &reporter_id,
+            &reported_user_id,
+            conversation_id.as_deref(),
+            &reason,
+        )?;
+
+        Ok::<_, db::DbError>(Filed::Report(Box::new(report)))
+    })
+    .await??;
+
+    match filed {
+        Filed::Report(report) => Ok(HttpResponse::Ok().json(report)),
+        Filed::MessageNotFound => Err(ApiError::NotFound(
+            "Message is not found in this room.".to_string(),
+        )),
+        Filed::UserNotFound => Err(ApiError::NotFound("User is not found.".to_string())),
+    }
+}
+
+/// Open reports of the room, oldest first, with the reported message in context.
+#[get("/{room_id}/reports")]
+pub async fn get_reports(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let room = authorize(&pool, &session, *room_id, Access::Moderator).await?;
+
+    let reports = web::block(move || {
+        let mut conn = pool.get()?;
+        db::reports::list_open_reports(&mut conn, &room.id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(reports))
+}
+
+#[derive(Deserialize)]
+struct ResolveReportData {
+    action: ReportAction,
+}
+
+enum Resolved {
+    Done(Box<Report>),
+    NotFound,
+    Closed,
+    NoMessage,
+    Forbidden(&'static str),
+}
+
+/// Resolve an open report by dismissing it, deleting the message, kicking or banning the user.
+#[post("/{room_id}/reports/{report_id}/resolve")]
+pub async fn resolve_report(
+    pool: web::Data<DbPool>,
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    data: web::Json<ResolveReportData>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    let (room_id, report_id) = path.into_inner();
+
+    let room = authorize(&pool, &session, room_id, Access::Moderator).await?;
+    let moderator_id = get_user_id(&session)?.to_string();
+    let action = data.action;
+
+    let resolved = web::block(move || {
+        let mut conn = pool.get()?;
+
+        // roles are checked under the same write lock that applies the action, so a role
+        // change in between can't let a refused kick or ban through
+        conn.immediate_transaction(|conn| {
+            let Some(report) = db::reports::find_report(conn, &room.id, &report_id.to_string())?
+            else {
+                return Ok(Resolved::NotFound);
+            };
+
+            if report.status != Report::OPEN {
+                return Ok(Resolved::Closed);
+            }
+
+            if !db::rooms_users::is_moderator(conn, &room, &moderator_id)? {
+                return Ok(Resolved::Forbidden(
+                    "Only the owner or a moderator can resolve reports.",
+                ));
+            }
+
+            let target = &report.reported_user_id;
+
+            if matches!(action, ReportAction::Kick | ReportAction::Ban) {
+                if *target == room.owner_id {
+                    return Ok(Resolved::Forbidden(
+                        "The owner can't be removed from the room.",
+                    ));
+                }
+
+                // only the owner can remove a moderator
+                let target_role = db::rooms_users::find_role(conn, target, &room.id)?;
+                if moderator_id != room.owner_id
+                    && target_role.as_deref() == Some(RoomUser::MODERATOR)
+                {
+                    return Ok(Resolved::Forbidden(
+                        "Only the owner can remove a moderator.",
+                    ));
+                }
+            }
+
+            if action == ReportAction::DeleteMessage && report.conversation_id.is_none() {
+                return Ok(Resolved::NoMessage);
+            }
+
+            // claim the report first, so only one moderator's action applies
+            if !db::reports::resolve_report(
+                conn,
+                &report,
+                action.as_str(),
+                &moderator_id,
+                action == ReportAction::DeleteMessage,
+            )? {
+                return Ok(Resolved::Closed);
+            }
+
+            match action {
+                ReportAction::Dismiss => {}
+                ReportAction::DeleteMessage => {
+                    if let Some(conversation_id) = &report.conversation_id {
+                        db::conversations::delete_conversation(conn, conversation_id)?;
+                    }
+                }
+                ReportAction::Kick => {
+                    db::rooms_users::exit_room(
+                        conn,
+                        Uuid::parse_str(target)?,
+                        Uuid::parse_str(&room.id)?,
+                    )?;
+                }
+                ReportAction::Ban => {
+                    db::rooms_users::ban(conn, target, &room.id, &moderator_id)?;
+                }
+            }
+
+            db::audit::record(
+                conn,
+                Some(&moderator_id),
+                &format!("report.{}", action.as_str()),
+                Some(&room.id),
+                Some(target),
+                json!({
+                    "report_id": report.id,
+                    "conversation_id": report.conversation_id,
+                    "reason": report.reason,
+                }),
+            )?;
+
+            Ok::<_, db::DbError>(Resolved::Done(Box::new(report)))
+        })
+    })
+    .await??;
+
+    let report = match resolved {
+        Resolved::Done(report) => report,
+        Resolved::NotFound => {
+            return Err(ApiError::NotFound(format!(
+                "Report {} is not found.",
+                report_id
+            )));
+        }
+        Resolved::Closed => {
+            return Err(ApiError::Conflict(
+                "The report is already resolved.".to_string(),
+            ));
+        }
+        Resolved::NoMessage => {
+            return Err(ApiError::Unprocessable(
+                "The report isn't about a message.".to_string(),
+            ));
+        }
+        Resolved::Forbidden(message) => {
+            return Err(ApiError::Forbidden(message.to_string()));
+        }
+    };
+
+    match action {
+        ReportAction::Dismiss => {}
+        ReportAction::DeleteMessage => {
+            chat_server
+                .send_message(
+                    json!({
+                        "type": "message_deleted",
+                        "data": {
+                            "room_id": report.room_id,
+                            "id": report.conversation_id,
+                        }
+                    })
+                    .to_string(),
+                    report.room_id.clone(),
+                    0,
+                )
+                .await;
+        }
+        ReportAction::Kick | ReportAction::Ban => {
+            chat_server
+                .announce(
+                    report.room_id.clone(),
+                    json!({
+                        "type": "exit_room",
+                        "data": {
+                            "room_id": report.room_id,
+                            "user_id": report.reported_user_id,
+                            "reason": action,
+                        }
+                    })
+                    .to_string(),
+                )
+                .await;
+
+            chat_server
+                .remove_member(report.room_id.clone(), report.reported_user_id.clone())
+                .await;
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Lift a ban so the user can join the room again.
+#[delete("/{room_id}/bans/{user_id}")]
+pub async fn unban_user(
+    pool: web::Data<DbPool>,
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (room_id, user_id) = path.into_inner();
+
+    let room = authorize(&pool, &session, room_id, Access::Moderator).await?;
+    let moderator_id = get_user_id(&session)?.to_string();
+
+    let unbanned = web::block(move || {
+        let mut conn = pool.get()?;
+        let user_id = user_id.to_string();
+
+        conn.transaction(|conn| {
+            if !db::rooms_users::unban(conn, &user_id, &room.id)? {
+                return Ok(false);
+            }
+
+            db::audit::record(
+                conn,
+                Some(&moderator_id),
+                "room.unban",
+                Some(&room.id),
+                Some(&user_id),
+                json!({}),
+            )?;
+
+            Ok::<_, db::DbError>(true)
+        })
+    })
+    .await??;
+
+    if !unbanned {
+        return Err(ApiError::NotFound(format!(
+            "User {} is not banned.",
+            user_id
+        )));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,29 +1,39 @@
-use std::str::FromStr;
-
 use crate::{
-    db,
+    config::Config,
+    db::{self, audit::AuditQuery},
+    error::ApiError,
+    models::{Room, RoomUser},
+    rate_limit::RateLimiter,
     server::ChatServerHandle,
     services,
     types::DbPool,
-    utils::{get_conn_id, get_user_id},
+    utils::get_user_id,
 };
 use actix_session::Session;
-use actix_web::{
-    delete, error::ErrorInternalServerError, get, post, web, Error, HttpRequest, HttpResponse,
-};
-use futures_util::TryFutureExt;
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
+use diesel::Connection;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
+#[derive(Deserialize)]
+struct RoomListQuery {
+    /// List the archived rooms instead.
+    #[serde(default)]
+    archived: bool,
+}
+
 #[get("")]
-pub async fn get_rooms(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+pub async fn get_rooms(
+    pool: web::Data<DbPool>,
+    query: web::Query<RoomListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let archived = query.archived;
     let rooms = web::block(move || {
         let mut conn = pool.get()?;
-        db::rooms::get_all_rooms(&mut conn)
+        db::rooms::get_all_rooms(&mut conn, archived)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
     Ok(HttpResponse::Ok().json(rooms))
 }
@@ -39,50 +49,26 @@ pub async fn create_room(
     data: web::Json<CreateRoomData>,
     session: Session,
     chat_server: web::Data<ChatServerHandle>,
-) -> Result<HttpResponse, Error> {
+    config: web::Data<Config>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> Result<HttpResponse, ApiError> {
     println!("create room with name: {}", data.room_name);
-    let user_id = get_user_id(&session);
-
-    // get room and user info
-    let (room_res, user_res) = tokio::join!(
-        web::block({
-            let pool = pool.clone();
-            move || {
-                let mut conn = pool.get()?;
-
-                db::rooms::create_room(&mut conn, &user_id, &data.room_name)
-            }
-        }),
-        web::block({
-            let pool = pool.clone();
-            move || {
-                let mut conn = pool.get()?;
-
-                db::users::find_user_by_uid(&mut conn, user_id)
-            }
-        })
-    );
-
-    let room = room_res?.map_err(ErrorInternalServerError)?;
-    let user = user_res?.map_err(ErrorInternalServerError)?;
+    let user_id = get_user_id(&session)?;
 
-    let room_id = Uuid::from_str(&room.id).unwrap();
+    rate_limiter.check_http(
+        &format!("room:user:{user_id}"),
+        config.rate_limit.room_creation,
+    )?;
 
-    // join the room
-    services::rooms::join_room(pool.clone(), user_id, room_id)
-        .await
-        .map_err(ErrorInternalServerError)?;
+    let room = services::rooms::create_room(pool, user_id, data.into_inner().room_name).await?;
 
-    let room = web::block(move || {
-        let mut conn = pool.get()?;
-        db::rooms::get_room(&mut conn, room_id)
-    })
-    .await?
-    .map_err(ErrorInternalServerError)?;
+    chat_server
+        .add_member(room.room.id.clone(), user_id.to_string())
+        .await;
 
     chat_server
-        .broadcast(
-            0,
+        .announce(
+            room.room.id.clone(),
             json!({
                 "type": "create_room",
                 "data": room,
@@ -98,30 +84,65 @@ pub async fn create_room(
 
 #[post("/{room_id}/join")]
 pub async fn join_room(
-    request: HttpRequest,
     pool: web::Data<DbPool>,
     session: Session,
     room_id: web::Path<Uuid>,
     chat_server: web::Data<ChatServerHandle>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
     let room_id = room_id.to_owned();
-    let user_id = get_user_id(&session);
+    let user_id = get_user_id(&session)?;
     // let conn_id = get_conn_id(&request);
 
-    let _ = services::rooms::join_room(pool.clone(), user_id, room_id)
-        .await
-        .map_err(ErrorInternalServerError);
+    let (room, banned) = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            let room = db::rooms::find_room(&mut conn, &room_id.to_string())?;
+            let banned =
+                db::rooms_users::is_banned(&mut conn, &user_id.to_string(), &room_id.to_string())?;
+
+            Ok::<_, db::DbError>((room, banned))
+        })
+        .await??
+    };
+
+    let Some(room) = room else {
+        return Err(ApiError::NotFound(format!(
+            "Room {} is not found.",
+            room_id
+        )));
+    };
+
+    check_archived(&room)?;
+
+    if banned {
+        return Err(ApiError::Forbidden(
+            "You're banned from this room.".to_string(),
+        ));
+    }
+
+    let joined = services::rooms::join_room(pool.clone(), user_id, room_id).await?;
+
+    if !joined {
+        return Err(ApiError::Conflict(
+            "You're already a member of this room.".to_string(),
+        ));
+    }
 
     // if !conn_id.is_err() {
-    let user = services::users::find_user_by_uid(pool, user_id)
-        .await
-        .map_err(ErrorInternalServerError)?
-        .unwrap();
+    let Some(user) = services::users::find_user_by_uid(pool, user_id).await? else {
+        return Err(ApiError::Internal(format!(
+            "signed in user {user_id} not found"
+        )));
+    };
 
     chat_server
-        .broadcast(
-            // conn_id.unwrap(),
-            0,
+        .add_member(room_id.to_string(), user_id.to_string())
+        .await;
+
+    chat_server
+        .announce(
+            room_id.to_string(),
             json!({
                 "type": "join_room",
                 "data": {
@@ -139,25 +160,27 @@ pub async fn join_room(
 
 #[post("/{room_id}/exit")]
 pub async fn exit_room(
-    request: HttpRequest,
     pool: web::Data<DbPool>,
     session: Session,
     room_id: web::Path<Uuid>,
     chat_server: web::Data<ChatServerHandle>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
     let room_id = room_id.to_owned();
-    let user_id = get_user_id(&session);
+    let user_id = get_user_id(&session)?;
     // let conn_id = get_conn_id(&request);
 
-    let _ = services::rooms::exit_room(pool, user_id, room_id)
-        .await
-        .map_err(ErrorInternalServerError);
+    let exited = services::rooms::exit_room(pool, user_id, room_id).await?;
 
-    // if !conn_id.is_err() {
+    if !exited {
+        return Err(ApiError::Conflict(
+            "You're not a member of this room.".to_string(),
+        ));
+    }
+
+    // the user's own connections hear about it too, before they leave the room
     chat_server
-        .broadcast(
-            // conn_id.unwrap(),
-            0,
+        .announce(
+            room_id.to_string(),
             json!({
                 "type": "exit_room",
                 "data": {
@@ -168,94 +191,389 @@ pub async fn exit_room(
             .to_string(),
         )
         .await;
-    // }
-    // send ws messages
+
+    chat_server
+        .remove_member(room_id.to_string(), user_id.to_string())
+        .await;
 
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Archive the room. Archived rooms are read-only and left out of the room list; only a site
+/// admin can delete them for good.
 #[delete("/{room_id}")]
 pub async fn delete_room(
-    request: HttpRequest,
     pool: web::Data<DbPool>,
     session: Session,
     room_id: web::Path<Uuid>,
     chat_server: web::Data<ChatServerHandle>,
-) -> Result<HttpResponse, Error> {
-    let room_id = room_id.to_owned();
-    let user_id = get_user_id(&session);
+) -> Result<HttpResponse, ApiError> {
+    set_archived(pool, session, room_id.into_inner(), chat_server, true).await
+}
+
+#[post("/{room_id}/unarchive")]
+pub async fn unarchive_room(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    set_archived(pool, session, room_id.into_inner(), chat_server, false).await
+}
+
+async fn set_archived(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: Uuid,
+    chat_server: web::Data<ChatServerHandle>,
+    archived: bool,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+
+    let room = authorize(&pool, &session, room_id, Access::Owner).await?;
+
+    if room.is_archived() == archived {
+        return Err(ApiError::Conflict(
+            if archived {
+                "Room is already archived."
+            } else {
+                "Room is not archived."
+            }
+            .to_string(),
+        ));
+    }
+
+    let room = web::block(move || {
+        let mut conn = pool.get()?;
+
+        conn.transaction(|conn| {
+            let room = db::rooms::set_archived(conn, &room.id, archived)?;
+            db::audit::record(
+                conn,
+                Some(&user_id.to_string()),
+                if archived {
+                    "room.archive"
+                } else {
+                    "room.unarchive"
+                },
+                Some(&room.id),
+                None,
+                json!({ "name": room.name }),
+            )?;
+
+            Ok::<_, db::DbError>(room)
+        })
+    })
+    .await??;
+
+    chat_server
+        .announce(
+            room.id.clone(),
+            json!({
+                "type": if archived { "archive_room" } else { "unarchive_room" },
+                "data": {
+                    "room": room,
+                }
+            })
+            .to_string(),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(room))
+}
+
+/// Fails if the room is archived, as archived rooms are read-only.
+pub fn check_archived(room: &Room) -> Result<(), ApiError> {
+    match &room.archived_at {
+        Some(archived_at) => Err(archived_error(archived_at)),
+        None => Ok(()),
+    }
+}
+
+pub fn archived_error(archived_at: &str) -> ApiError {
+    ApiError::Conflict("Room is archived.".to_string())
+        .with_details(json!({ "archived_at": archived_at }))
+}
 
-    let room = {
+#[get("/{room_id}")]
+pub async fn get_room(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let room_id = room_id.to_owned();
+    let user_id = get_user_id(&session)?.to_string();
+    let (room, blocked_ids) = {
         let pool = pool.clone();
-        let room_id = room_id.clone();
 
         web::block(move || {
             let mut conn = pool.get()?;
 
-            db::rooms::get_room(&mut conn, room_id)
+            let room = db::rooms::get_room(&mut conn, room_id)?;
+            let blocked_ids = db::blocks::blocked_ids(&mut conn, &user_id)?;
+
+            Ok::<_, db::DbError>((room, blocked_ids))
         })
-        .await?
-        .map_err(ErrorInternalServerError)?
+        .await??
     };
 
-    if room.is_none() {
-        return Ok(HttpResponse::NotFound().json(json!({
-            "message": format!("Room {} is not found.", room_id)
-        })));
+    match room {
+        Some(mut room) => {
+            // hide messages from users the requester blocked
+            room.conversations
+                .retain(|conversation| !blocked_ids.contains(&conversation.user_id));
+
+            Ok(HttpResponse::Ok().json(room))
+        }
+        None => Err(ApiError::NotFound(format!(
+            "Room {} is not found.",
+            room_id
+        ))),
     }
+}
 
-    let room = room.unwrap();
+/// Who may use a room endpoint.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Access {
+    Owner,
+    /// The owner or a moderator.
+    Moderator,
+    /// Anyone in the room.
+    Member,
+}
 
-    if room.room.owner_id != user_id.to_string() {
-        return Ok(HttpResponse::Unauthorized().json(json!({
-            "message": "You're not the owner."
-        })));
+impl Access {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Access::Owner => "owner",
+            Access::Moderator => "moderator",
+            Access::Member => "member",
+        }
     }
+}
 
-    let res = web::block(move || {
+/// Load the room and check the signed in user's access to it.
+///
+/// Denied attempts are written to the audit log.
+pub async fn authorize(
+    pool: &web::Data<DbPool>,
+    session: &Session,
+    room_id: Uuid,
+    access: Access,
+) -> Result<Room, ApiError> {
+    let user_id = get_user_id(session)?.to_string();
+    let pool = pool.clone();
+
+    let (room, allowed) = web::block(move || {
         let mut conn = pool.get()?;
+        let Some(room) = db::rooms::find_room(&mut conn, &room_id.to_string())? else {
+            return Ok((None, false));
+        };
+
+        let allowed = match access {
+            Access::Owner => room.owner_id == user_id,
+            Access::Moderator => db::rooms_users::is_moderator(&mut conn, &room, &user_id)?,
+            Access::Member => {
+                room.owner_id == user_id
+                    || db::rooms_users::find_role(&mut conn, &user_id, &room.id)?.is_some()
+            }
+        };
+
+        if !allowed {
+            db::audit::record(
+                &mut conn,
+                Some(&user_id),
+                "room.access_denied",
+                Some(&room.id),
+                None,
+                json!({ "required": access.as_str() }),
+            )?;
+        }
+
+        Ok::<_, db::DbError>((Some(room), allowed))
+    })
+    .await??;
+
+    let Some(room) = room else {
+        return Err(ApiError::NotFound(format!(
+            "Room {} is not found.",
+            room_id
+        )));
+    };
+
+    if !allowed {
+        let message = match access {
+            Access::Owner => "You're not the owner.",
+            Access::Moderator => "You're not a moderator of this room.",
+            Access::Member => "You're not a member of this room.",
+        };
+        return Err(ApiError::Forbidden(message.to_string()));
+    }
+
+    Ok(room)
+}
+
+/// Longest slow mode interval an owner can set, six hours.
+const MAX_SLOW_MODE_SECONDS: i32 = 6 * 60 * 60;
+
+#[derive(Deserialize)]
+struct UpdateRoomData {
+    slow_mode_seconds: i32,
+}
+
+#[patch("/{room_id}")]
+pub async fn update_room(
+    pool: web::Data<DbPool>,
+    session: Session,
+    room_id: web::Path<Uuid>,
+    data: web::Json<UpdateRoomData>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    let room_id = room_id.to_owned();
+    let user_id = get_user_id(&session)?;
+    let slow_mode_seconds = data.slow_mode_seconds;
+
+    if !(0..=MAX_SLOW_MODE_SECONDS).contains(&slow_mode_seconds) {
+        return Err(ApiError::Unprocessable(format!(
+            "Slow mode must be between 0 and {MAX_SLOW_MODE_SECONDS} seconds."
+        )));
+    }
+
+    let room = authorize(&pool, &session, room_id, Access::Owner).await?;
+    check_archived(&room)?;
 
-        db::rooms::delete_room(&mut conn, room_id)
+    let room = web::block(move || {
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let room = db::rooms::set_slow_mode(conn, room_id, slow_mode_seconds)?;
+            db::audit::record(
+                conn,
+                Some(&user_id.to_string()),
+                "room.update",
+                Some(&room.id),
+                None,
+                json!({ "slow_mode_seconds": slow_mode_seconds }),
+            )?;
+
+            Ok::<_, db::DbError>(room)
+        })
     })
-    .await?
-    .map_err(ErrorInternalServerError)?;
-
-    chat_server.broadcast(
-        0,
-        json!({
-            "type": "delete_room",
-            "data": {
-                "room_id": room_id.to_string(),
+    .await??;
+
+    chat_server
+        .announce(
+            room.id.clone(),
+            json!({
+                "type": "room_updated",
+                "data": room,
+            })
+            .to_string(),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(room))
+}
+
+#[put("/{room_id}/moderators/{user_id}")]
+pub async fn add_moderator(
+    pool: web::Data<DbPool>,
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    set_member_role(pool, session, path, chat_server, RoomUser::MODERATOR).await
+}
+
+#[delete("/{room_id}/moderators/{user_id}")]
+pub async fn remove_moderator(
+    pool: web::Data<DbPool>,
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    chat_server: web::Data<ChatServerHandle>,
+) -> Result<HttpResponse, ApiError> {
+    set_member_role(pool, session, path, chat_server, RoomUser::MEMBER).await
+}
+
+async fn set_member_role(
+    pool: web::Data<DbPool>,
+    session: Session,
+    path: web::Path<(Uuid, Uuid)>,
+    chat_server: web::Data<ChatServerHandle>,
+    role: &'static str,
+) -> Result<HttpResponse, ApiError> {
+    let (room_id, member_id) = path.into_inner();
+    let user_id = get_user_id(&session)?;
+
+    let room = authorize(&pool, &session, room_id, Access::Owner).await?;
+    check_archived(&room)?;
+
+    let updated = web::block(move || {
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            if !db::rooms_users::set_role(conn, member_id, room_id, role)? {
+                return Ok(false);
             }
+
+            db::audit::record(
+                conn,
+                Some(&user_id.to_string()),
+                "room.set_role",
+                Some(&room_id.to_string()),
+                Some(&member_id.to_string()),
+                json!({ "role": role }),
+            )?;
+
+            Ok::<_, db::DbError>(true)
         })
-        .to_string(),
-    ).await;
+    })
+    .await??;
+
+    if !updated {
+        return Err(ApiError::NotFound(format!(
+            "User {} is not a member of the room.",
+            member_id
+        )));
+    }
+
+    chat_server
+        .send_message(
+            json!({
+                "type": "role_updated",
+                "data": {
+                    "room_id": room_id.to_string(),
+                    "user_id": member_id.to_string(),
+                    "role": role,
+                }
+            })
+            .to_string(),
+            room_id.to_string(),
+            0,
+        )
+        .await;
 
     Ok(HttpResponse::Ok().finish())
 }
 
-#[get("/{room_id}")]
-pub async fn get_room(
+/// The room's audit log, for its owner.
+#[get("/{room_id}/audit")]
+pub async fn get_room_audit_events(
     pool: web::Data<DbPool>,
+    session: Session,
     room_id: web::Path<Uuid>,
-) -> Result<HttpResponse, Error> {
-    let room_id = room_id.to_owned();
-    let room = {
-        let pool = pool.clone();
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let room = authorize(&pool, &session, *room_id, Access::Owner).await?;
 
-        web::block(move || {
-            let mut conn = pool.get()?;
-
-            db::rooms::get_room(&mut conn, room_id)
-        })
-        .await?
-        .map_err(ErrorInternalServerError)?
+    let query = AuditQuery {
+        room_id: Some(room.id),
+        ..query.into_inner()
     };
 
-    match room {
-        Some(room) => Ok(HttpResponse::Ok().json(room)),
-        None => Ok(HttpResponse::NotFound().json(json!({
-            "message": format!("Room {} is not found.", room_id)
-        }))),
-    }
+    let events = web::block(move || {
+        let mut conn = pool.get()?;
+        db::audit::list_events(&mut conn, &query)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(events))
 }
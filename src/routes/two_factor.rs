@@ -0,0 +1,301 @@
+use actix_session::Session;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    db,
+    error::ApiError,
+    login_guard::LoginGuard,
+    password,
+    rate_limit::RateLimiter,
+    totp,
+    types::DbPool,
+    utils::{get_client_ip, get_user_id},
+};
+
+use super::auth::{
+    check_current_password, check_suspended, record_failed_login, too_many_failed_sign_ins,
+};
+
+/// Session state of a sign in whose password was correct but whose second factor is still
+/// outstanding.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingTwoFactor {
+    user_id: Uuid,
+    started_at: i64,
+}
+
+impl PendingTwoFactor {
+    pub fn new(user_id: Uuid) -> Self {
+        Self {
+            user_id,
+            started_at: Utc::now().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifySignInData {
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+/// Complete a sign in with either a TOTP code or a recovery code.
+#[post("/signin/2fa")]
+pub async fn verify_sign_in(
+    request: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    login_guard: web::Data<LoginGuard>,
+    rate_limiter: web::Data<RateLimiter>,
+    session: Session,
+    data: web::Json<VerifySignInData>,
+) -> Result<HttpResponse, ApiError> {
+    rate_limiter.check_http(
+        &format!("auth:ip:{}", get_client_ip(&request)),
+        config.rate_limit.auth_per_ip,
+    )?;
+
+    let pending = session
+        .get::<PendingTwoFactor>("pending_2fa")
+        .unwrap_or(None)
+        .filter(|pending| {
+            Utc::now().timestamp() - pending.started_at <= config.two_factor.pending_ttl_secs
+        });
+
+    let Some(pending) = pending else {
+        session.remove("pending_2fa");
+        return Err(ApiError::Unauthorized(
+            "No sign in is waiting for two-factor verification.".to_string(),
+        ));
+    };
+
+    let user_id = pending.user_id;
+    let user = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            db::users::find_user_by_uid(&mut conn, user_id)
+        })
+        .await??
+    };
+
+    let Some(user) = user.filter(|user| user.totp_enabled) else {
+        session.remove("pending_2fa");
+        return Err(ApiError::Unauthorized(
+            "No sign in is waiting for two-factor verification.".to_string(),
+        ));
+    };
+
+    let ip = get_client_ip(&request);
+
+    if let Err(retry_after) = login_guard.begin_attempt(&user.username, &ip) {
+        return Err(too_many_failed_sign_ins(retry_after));
+    }
+
+    let VerifySignInData {
+        code,
+        recovery_code,
+    } = data.0;
+
+    let verified = match (code, recovery_code, user.totp_secret.as_deref()) {
+        (Some(code), _, Some(secret)) => match totp::verify(secret, &code, user.totp_last_step) {
+            // only one of concurrent submissions of the same code gets to claim its step
+            Some(step) => {
+                let pool = pool.clone();
+                web::block(move || {
+                    let mut conn = pool.get()?;
+                    db::two_factor::set_last_step(&mut conn, user_id, step)
+                })
+                .await??
+            }
+            None => false,
+        },
+        (None, Some(recovery_code), _) => {
+            let code_hash = password::hash_token(&totp::normalize_recovery_code(&recovery_code));
+            let pool = pool.clone();
+            web::block(move || {
+                let mut conn = pool.get()?;
+                db::two_factor::use_recovery_code(&mut conn, user_id, &code_hash)
+            })
+            .await??
+        }
+        _ => false,
+    };
+
+    if !verified {
+        record_failed_login(pool, user.username, Some(user.id), ip, "wrong_2fa_code").await;
+
+        return Err(ApiError::Unauthorized(
+            "Invalid two-factor code.".to_string(),
+        ));
+    }
+
+    login_guard.refund(&user.username, &ip);
+    login_guard.record_success(&user.username);
+
+    session.remove("pending_2fa");
+
+    check_suspended(&user)?;
+
+    session.insert("user_id", user.id.clone())?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Start enrolment by generating a new secret. Two-factor is enabled once a code generated
+/// from it is confirmed.
+#[post("/user/2fa/enroll")]
+pub async fn enroll(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    session: Session,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+
+    let user = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            db::users::find_user_by_uid(&mut conn, user_id)
+        })
+        .await??
+    };
+
+    let Some(user) = user else {
+        return Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        )));
+    };
+
+    if user.totp_enabled {
+        return Err(ApiError::Conflict(
+            "Two-factor authentication is already enabled.".to_string(),
+        ));
+    }
+
+    let secret = totp::generate_secret();
+
+    {
+        let secret = secret.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            db::two_factor::set_pending_secret(&mut conn, user_id, &secret)
+        })
+        .await??;
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "secret": secret,
+        "otpauth_uri": totp::otpauth_uri(&config.two_factor.issuer, &user.username, &secret),
+    })))
+}
+
+#[derive(Deserialize)]
+struct ConfirmData {
+    code: String,
+}
+
+/// Enable two-factor and hand out recovery codes. The codes are only ever shown here.
+#[post("/user/2fa/confirm")]
+pub async fn confirm(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    session: Session,
+    data: web::Json<ConfirmData>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+
+    let user = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            db::users::find_user_by_uid(&mut conn, user_id)
+        })
+        .await??
+    };
+
+    let Some(user) = user else {
+        return Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        )));
+    };
+
+    let secret = match user.totp_secret {
+        Some(secret) if !user.totp_enabled => secret,
+        _ => {
+            return Err(ApiError::Conflict(
+                "No two-factor enrolment in progress.".to_string(),
+            ));
+        }
+    };
+
+    let Some(step) = totp::verify(&secret, &data.code, None) else {
+        return Err(ApiError::Unprocessable(
+            "Invalid two-factor code.".to_string(),
+        ));
+    };
+
+    let recovery_codes = totp::generate_recovery_codes(config.two_factor.recovery_code_count);
+    let code_hashes = recovery_codes
+        .iter()
+        .map(|code| password::hash_token(&totp::normalize_recovery_code(code)))
+        .collect();
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        db::two_factor::enable(&mut conn, user_id, step, code_hashes)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "recovery_codes": recovery_codes,
+    })))
+}
+
+#[derive(Deserialize)]
+struct DisableData {
+    password: String,
+}
+
+#[post("/user/2fa/disable")]
+pub async fn disable(
+    request: HttpRequest,
+    pool: web::Data<DbPool>,
+    login_guard: web::Data<LoginGuard>,
+    session: Session,
+    data: web::Json<DisableData>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+
+    let user = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            db::users::find_user_by_uid(&mut conn, user_id)
+        })
+        .await??
+    };
+
+    let Some(user) = user else {
+        return Err(ApiError::NotFound(format!(
+            "User {} does not exist.",
+            user_id
+        )));
+    };
+
+    check_current_password(&request, &login_guard, &user, data.0.password).await?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        db::two_factor::disable(&mut conn, user_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
@@ -1,33 +1,127 @@
-use actix_web::{error::ErrorInternalServerError, get, web, Error, HttpResponse};
-use serde_json::json;
+use actix_session::Session;
+use actix_web::{delete, get, put, web, HttpResponse};
 use uuid::Uuid;
 
-use crate::{db, types::DbPool};
+use crate::{db, error::ApiError, server::ChatServerHandle, types::DbPool, utils::get_user_id};
 
-#[get("/users/{user_id}")]
+/// Public profile of a user.
+#[get("/{user_id}")]
 pub async fn get_user_by_id(
     pool: web::Data<DbPool>,
     id: web::Path<Uuid>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
     let user_id = id.to_owned();
     let user = web::block(move || {
         let mut conn = pool.get()?;
 
         db::users::find_user_by_uid(&mut conn, user_id)
     })
-    .await?
-    .map_err(ErrorInternalServerError)?;
-
-    if let Some(user) = user {
-        Ok(HttpResponse::Ok().json(user))
-    } else {
-        let res = HttpResponse::NotFound().body(
-            json!({
-                "error": 404,
-                "message": format!("No user found with phone: {id}")
-            })
-            .to_string(),
-        );
-        Ok(res)
+    .await??;
+
+    match user {
+        Some(user) => Ok(HttpResponse::Ok().json(user)),
+        None => Err(ApiError::NotFound(format!(
+            "No user found with id: {user_id}"
+        ))),
+    }
+}
+
+/// Users the signed in user blocked.
+#[get("/blocks")]
+pub async fn get_blocks(
+    pool: web::Data<DbPool>,
+    session: Session,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?.to_string();
+
+    let users = web::block(move || {
+        let mut conn = pool.get()?;
+
+        db::blocks::list_blocked_users(&mut conn, &user_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Block a user: their messages are hidden from the room history and live updates of the
+/// signed in user.
+#[put("/blocks/{user_id}")]
+pub async fn block_user(
+    pool: web::Data<DbPool>,
+    chat_server: web::Data<ChatServerHandle>,
+    session: Session,
+    blocked_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?;
+    let blocked_id = blocked_id.into_inner();
+
+    if blocked_id == user_id {
+        return Err(ApiError::BadRequest(
+            "You can't block yourself.".to_string(),
+        ));
     }
+
+    let blocked = {
+        let user_id = user_id.to_string();
+
+        web::block(move || {
+            let mut conn = pool.get()?;
+
+            let blocked = db::users::find_user_by_uid(&mut conn, blocked_id)?
+                .filter(|user| user.id != db::users::DELETED_USER_ID);
+            let Some(blocked) = blocked else {
+                return Ok(None);
+            };
+            db::blocks::block(&mut conn, &user_id, &blocked.id)?;
+
+            Ok::<_, db::DbError>(Some(blocked))
+        })
+        .await??
+    };
+
+    let Some(blocked) = blocked else {
+        return Err(ApiError::NotFound(format!(
+            "No user found with id: {blocked_id}"
+        )));
+    };
+
+    chat_server
+        .block(user_id.to_string(), blocked.id.clone())
+        .await;
+
+    Ok(HttpResponse::Ok().json(blocked))
+}
+
+#[delete("/blocks/{user_id}")]
+pub async fn unblock_user(
+    pool: web::Data<DbPool>,
+    chat_server: web::Data<ChatServerHandle>,
+    session: Session,
+    blocked_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = get_user_id(&session)?.to_string();
+    let blocked_id = blocked_id.to_string();
+
+    let unblocked = {
+        let user_id = user_id.clone();
+        let blocked_id = blocked_id.clone();
+
+        web::block(move || {
+            let mut conn = pool.get()?;
+
+            db::blocks::unblock(&mut conn, &user_id, &blocked_id)
+        })
+        .await??
+    };
+
+    if !unblocked {
+        return Err(ApiError::NotFound(format!(
+            "User {blocked_id} is not blocked."
+        )));
+    }
+
+    chat_server.unblock(user_id, blocked_id).await;
+
+    Ok(HttpResponse::NoContent().finish())
 }
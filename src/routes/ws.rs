@@ -1,53 +1,248 @@
 use std::{
+    collections::{HashMap, HashSet},
     pin::pin,
     time::{Duration, Instant},
 };
 
-use actix_session::Session;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
-use actix_ws::{AggregatedMessage, Message};
+use actix_ws::{AggregatedMessage, CloseCode, CloseReason, ProtocolError};
+use diesel::SqliteConnection;
 use futures_util::{
     future::{select, Either},
     StreamExt as _,
 };
+use serde::Deserialize;
 use serde_json::json;
-use tokio::{sync::mpsc, task::spawn_local, time::interval};
+use tokio::{task::spawn_local, time::interval};
+
+use crate::{
+    config::Config,
+    db::{self, DbError},
+    error::ApiError,
+    rate_limit::RateLimiter,
+    resume,
+    server::{self, ChatServerHandle, CloseCause},
+    types::DbPool,
+    utils::get_user_id,
+    ConnId, Msg, RoomId,
+};
+
+use super::auth::check_suspended;
+
+/// Where a resuming client left off.
+struct Resume {
+    /// The connection its resume token was issued to, if the token is valid.
+    resumed_conn: Option<ConnId>,
+    /// The last `seq` it saw in each room.
+    last_seqs: HashMap<RoomId, i64>,
+}
+
+/// What a new connection is told about its rooms.
+#[derive(Default)]
+struct CatchUp {
+    /// The latest `seq` of each room the user is in that has had any events.
+    seqs: HashMap<RoomId, i64>,
+    /// Events a resuming client missed.
+    events: Vec<Msg>,
+    /// Rooms a resuming client is too far behind in, or can't replay, and has to refetch.
+    refetch: Vec<RoomId>,
+}
+
+fn catch_up(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    resume: Option<Resume>,
+    max_replay: i64,
+) -> Result<CatchUp, DbError> {
+    let rooms: HashSet<RoomId> = db::rooms::get_user_joined_rooms(conn, user_id.to_string())?
+        .into_iter()
+        .map(|room| room.id)
+        .collect();
+
+    let mut catch_up = CatchUp {
+        seqs: db::room_events::last_seqs(conn, &rooms.iter().cloned().collect::<Vec<_>>())?,
+        ..CatchUp::default()
+    };
+
+    let Some(resume) = resume else {
+        return Ok(catch_up);
+    };
 
-use crate::{server::ChatServerHandle, utils::get_user_id, ConnId};
+    let blocked: HashSet<String> = db::blocks::blocked_ids(conn, user_id)?
+        .into_iter()
+        .collect();
 
-/// How often heartbeat pings are sent
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+    for (room_id, after) in resume.last_seqs {
+        // rooms the user left since are of no concern
+        if !rooms.contains(&room_id) {
+            continue;
+        }
+
+        if resume.resumed_conn.is_none() {
+            catch_up.refetch.push(room_id);
+            continue;
+        }
+
+        let events = db::room_events::since(conn, &room_id, after, max_replay + 1)?;
+
+        // older events than the first one we have are gone
+        let complete = events.first().is_none_or(|event| event.seq == after + 1);
+        if events.len() as i64 > max_replay || !complete {
+            catch_up.refetch.push(room_id);
+            continue;
+        }
 
-/// How long before lack of client response causes a timeout
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+        catch_up.events.extend(
+            events
+                .into_iter()
+                .filter(|event| {
+                    event
+                        .sender_id
+                        .as_ref()
+                        .is_none_or(|sender| !blocked.contains(sender))
+                })
+                .map(|event| Msg::from(event.payload)),
+        );
+    }
 
+    Ok(catch_up)
+}
+
+/// Tell the client its connection couldn't be set up, and close it.
+async fn close_with_error(mut session: actix_ws::Session) {
+    let _ = session
+        .text(
+            json!({
+                "type": "error",
+                "data": {
+                    "message": "Couldn't load your rooms. Try again later.",
+                }
+            })
+            .to_string(),
+        )
+        .await;
+    let _ = session
+        .close(Some(CloseReason {
+            code: CloseCode::Error,
+            description: Some("internal error".to_string()),
+        }))
+        .await;
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn chat_ws_handler(
     chat_server: ChatServerHandle,
     mut session: actix_ws::Session,
     msg_stream: actix_ws::MessageStream,
     user_id: String,
+    resume: Option<Resume>,
+    config: web::Data<Config>,
+    rate_limiter: web::Data<RateLimiter>,
+    pool: web::Data<DbPool>,
 ) {
     log::info!("connected");
-    let mut name = None;
     let mut last_heartbeat = Instant::now();
-    let mut interval = interval(HEARTBEAT_INTERVAL);
+    let client_timeout = Duration::from_secs(config.socket.client_timeout_secs);
+    let mut interval = interval(Duration::from_secs(
+        config.socket.heartbeat_interval_secs.max(1),
+    ));
 
-    let (conn_tx, mut conn_rx) = mpsc::unbounded_channel();
+    let (conn_tx, conn_rx) =
+        server::outbox(config.socket.queue_capacity, config.socket.overflow_policy);
 
-    let conn_id = chat_server.connect(conn_tx, user_id).await;
+    let conn_id = match chat_server.connect(conn_tx, user_id.clone()).await {
+        Ok(conn_id) => conn_id,
+        Err(err) => {
+            log::error!("failed to register connection: {err}");
+            chat_server.count_close(CloseCause::Error);
+            close_with_error(session).await;
+            return;
+        }
+    };
+
+    if let Some(Resume {
+        resumed_conn: Some(resumed_conn),
+        ..
+    }) = &resume
+    {
+        log::info!("conn {conn_id} resumes conn {resumed_conn}");
+    }
+    let resumed = resume.is_some();
+
+    // the connection is registered first, so nothing sent from here on is missed; events may
+    // arrive twice, which clients tell by their `seq`
+    let query_user_id = user_id.clone();
+    let max_replay = config.resume.max_replay;
+    let catch_up = web::block(move || {
+        let mut conn = pool.get()?;
+        catch_up(&mut conn, &query_user_id, resume, max_replay)
+    })
+    .await
+    .unwrap_or_else(|err| Err(err.into()));
+
+    let catch_up = match catch_up {
+        Ok(catch_up) => catch_up,
+        Err(err) => {
+            log::error!("failed to load the rooms of conn {conn_id}: {err}");
+            chat_server.disconnect(conn_id).await;
+            chat_server.count_close(CloseCause::Error);
+            close_with_error(session).await;
+            return;
+        }
+    };
 
-    session
+    let init = session
         .text(
             json!({
                 "type":"init",
                 "data": {
                     "conn_id": conn_id.to_string(),
+                    "resume_token": resume::issue(&config.resume.token_secret, conn_id, &user_id),
+                    "seqs": catch_up.seqs,
                 }
             })
             .to_string(),
         )
-        .await
-        .unwrap();
+        .await;
+
+    if init.is_err() {
+        chat_server.disconnect(conn_id).await;
+        chat_server.count_close(CloseCause::Lost);
+        return;
+    }
+
+    if resumed {
+        let mut sent = Ok(());
+        let replayed = catch_up.events.len();
+
+        for event in catch_up.events {
+            sent = session.text(event).await;
+            if sent.is_err() {
+                break;
+            }
+        }
+
+        if sent.is_ok() {
+            sent = session
+                .text(
+                    json!({
+                        "type": "resumed",
+                        "data": {
+                            "replayed": replayed,
+                            "refetch": catch_up.refetch,
+                        }
+                    })
+                    .to_string(),
+                )
+                .await;
+        }
+
+        if sent.is_err() {
+            chat_server.disconnect(conn_id).await;
+            chat_server.count_close(CloseCause::Lost);
+            return;
+        }
+    }
 
     let msg_stream = msg_stream
         .max_frame_size(128 * 1024)
@@ -56,7 +251,7 @@ async fn chat_ws_handler(
 
     let mut msg_stream = pin!(msg_stream);
 
-    let close_reason = loop {
+    let (cause, close_reason) = loop {
         let tick = pin!(interval.tick());
         let msg_rx = pin!(conn_rx.recv());
 
@@ -66,88 +261,224 @@ async fn chat_ws_handler(
             Either::Left((Either::Left((Some(Ok(msg)), _)), _)) => {
                 log::debug!("msg: {msg:?}");
 
+                // anything from the client shows it is still there
+                last_heartbeat = Instant::now();
+
                 match msg {
                     AggregatedMessage::Ping(bytes) => {
-                        last_heartbeat = Instant::now();
-                        session.pong(&bytes).await.unwrap();
+                        if session.pong(&bytes).await.is_err() {
+                            break (CloseCause::Lost, None);
+                        }
                     }
 
-                    AggregatedMessage::Pong(_) => {
-                        last_heartbeat = Instant::now();
-                    }
+                    AggregatedMessage::Pong(_) => {}
 
                     AggregatedMessage::Text(text) => {
-                        process_text_msg(&chat_server, &mut session, &text, conn_id, &mut name)
-                            .await;
+                        let limit = config.rate_limit.socket_frames;
+                        if let Err(retry_after) =
+                            rate_limiter.check(&format!("socket:conn:{conn_id}"), limit)
+                        {
+                            let _ = session
+                                .text(
+                                    json!({
+                                        "type": "rate_limited",
+                                        "data": {
+                                            "retry_after_ms": retry_after.as_millis() as u64,
+                                        }
+                                    })
+                                    .to_string(),
+                                )
+                                .await;
+                            continue;
+                        }
+
+                        process_text_msg(&chat_server, &mut session, &text, conn_id).await;
                     }
 
                     AggregatedMessage::Binary(_bin) => {
                         log::warn!("unexpected binary message");
                     }
 
-                    AggregatedMessage::Close(reason) => break reason,
+                    AggregatedMessage::Close(reason) => break (CloseCause::Client, reason),
                 }
             }
 
             // client WebSocket stream error
             Either::Left((Either::Left((Some(Err(err)), _)), _)) => {
-                log::error!("{}", err);
-                break None;
+                log::warn!("conn {conn_id}: {err}");
+
+                break match err {
+                    ProtocolError::Io(_) => (CloseCause::Lost, None),
+                    ProtocolError::Overflow => (
+                        CloseCause::Protocol,
+                        Some(CloseReason {
+                            code: CloseCode::Size,
+                            description: Some("frame too large".to_string()),
+                        }),
+                    ),
+                    _ => (
+                        CloseCause::Protocol,
+                        Some(CloseReason {
+                            code: CloseCode::Protocol,
+                            description: Some("protocol error".to_string()),
+                        }),
+                    ),
+                };
             }
 
             // client WebSocket stream ended
-            Either::Left((Either::Left((None, _)), _)) => break None,
+            Either::Left((Either::Left((None, _)), _)) => break (CloseCause::Lost, None),
 
             // chat messages received from other room participants
             Either::Left((Either::Right((Some(chat_msg), _)), _)) => {
-                println!("from others:{}", chat_msg);
-                session.text(chat_msg).await.unwrap();
+                log::debug!("conn {conn_id}: sending {chat_msg}");
+                if session.text(chat_msg).await.is_err() {
+                    break (CloseCause::Lost, None);
+                }
             }
 
-            // all connection's message senders were dropped
-            Either::Left((Either::Right((None, _)), _)) => unreachable!(
-                "all connection message senders were dropped; chat server may have panicked"
-            ),
+            // the chat server dropped this connection, e.g. because the account was deleted or
+            // the client couldn't keep up
+            Either::Left((Either::Right((None, _)), _)) => {
+                break (
+                    CloseCause::Server,
+                    Some(conn_rx.close_reason().unwrap_or(CloseReason {
+                        code: CloseCode::Policy,
+                        description: Some("disconnected by server".to_string()),
+                    })),
+                );
+            }
 
             // heartbeat internal tick
             Either::Right((_inst, _)) => {
-                // if no heartbeat ping/pong received recently, close the connection
-                if Instant::now().duration_since(last_heartbeat) > CLIENT_TIMEOUT {
-                    log::info!(
-                        "client has not sent heartbeat in over {CLIENT_TIMEOUT:?}; disconnecting"
+                // if nothing was received recently, close the connection
+                if Instant::now().duration_since(last_heartbeat) > client_timeout {
+                    break (
+                        CloseCause::Timeout,
+                        Some(CloseReason {
+                            code: CloseCode::Away,
+                            description: Some("heartbeat timeout".to_string()),
+                        }),
                     );
-                    break None;
                 }
 
                 // send heartbeat ping
                 let _ = session.ping(b"").await;
+                if config.socket.app_ping {
+                    let _ = session.text(json!({ "type": "ping" }).to_string()).await;
+                }
             }
         };
     };
 
-    println!("disconnect:: {conn_id}");
+    match &close_reason {
+        Some(CloseReason { code, description }) => log::info!(
+            "conn {conn_id} closed ({cause}): {} {}",
+            u16::from(*code),
+            description.as_deref().unwrap_or_default()
+        ),
+        None => log::info!("conn {conn_id} closed ({cause})"),
+    }
 
     chat_server.disconnect(conn_id).await;
+    chat_server.count_close(cause);
 
     let _ = session.close(close_reason).await;
 }
 
+/// Query parameters of a client resuming after a reconnect.
+#[derive(Debug, Deserialize)]
+pub struct ResumeQuery {
+    /// The `resume_token` from the `init` frame of the connection it lost.
+    resume_token: Option<String>,
+    /// The last `seq` it saw in each room, as `<room_id>:<seq>` pairs separated by commas.
+    last_seqs: Option<String>,
+}
+
+impl ResumeQuery {
+    fn last_seqs(&self) -> Result<HashMap<RoomId, i64>, ApiError> {
+        let Some(last_seqs) = &self.last_seqs else {
+            return Ok(HashMap::new());
+        };
+
+        last_seqs
+            .split(',')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                pair.rsplit_once(':')
+                    .and_then(|(room_id, seq)| Some((room_id.to_string(), seq.parse().ok()?)))
+                    .ok_or_else(|| {
+                        ApiError::BadRequest(format!("Invalid last_seqs entry {pair:?}."))
+                    })
+            })
+            .collect()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn chat_ws(
     req: HttpRequest,
     stream: web::Payload,
+    query: web::Query<ResumeQuery>,
     http_session: actix_session::Session,
     chat_server: web::Data<ChatServerHandle>,
+    config: web::Data<Config>,
+    rate_limiter: web::Data<RateLimiter>,
+    pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, Error> {
     println!("here!");
-    let user_id = get_user_id(&http_session).to_string();
+    let user_id = get_user_id(&http_session)?;
+
+    if chat_server.is_shutting_down() {
+        return Err(ApiError::Unavailable(
+            "The server is restarting. Try again shortly.".to_string(),
+        )
+        .into());
+    }
+
+    let query_pool = pool.clone();
+    let user = web::block(move || {
+        let mut conn = query_pool.get()?;
+        db::users::find_user_by_uid(&mut conn, user_id)
+    })
+    .await?
+    .map_err(ApiError::from)?;
 
-    let (res, mut session, mut msg_stream) = actix_ws::handle(&req, stream)?;
+    let Some(user) = user else {
+        return Err(ApiError::Unauthorized("Signin required.".to_string()).into());
+    };
+
+    check_suspended(&user)?;
+
+    let user_id = user.id;
+
+    let resume = if query.resume_token.is_some() || query.last_seqs.is_some() {
+        Some(Resume {
+            resumed_conn: query.resume_token.as_deref().and_then(|token| {
+                resume::verify(
+                    &config.resume.token_secret,
+                    token,
+                    &user_id,
+                    config.resume.token_ttl_secs,
+                )
+            }),
+            last_seqs: query.last_seqs()?,
+        })
+    } else {
+        None
+    };
+
+    let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
 
     spawn_local(chat_ws_handler(
         (**chat_server).clone(),
         session,
         msg_stream,
         user_id,
+        resume,
+        config,
+        rate_limiter,
+        pool,
     ));
 
     // actix_web::rt::spawn(async move {
@@ -176,7 +507,6 @@ async fn process_text_msg(
     session: &mut actix_ws::Session,
     text: &str,
     conn: ConnId,
-    name: &mut Option<String>,
 ) {
     let msg = text.trim();
 
@@ -194,18 +524,27 @@ async fn process_text_msg(
                 session.text(json!(rooms).to_string()).await.unwrap();
             }
 
-            "/join" => match cmd_args.next() {
-                Some(room) => {
-                    log::info!("conn {conn} joining room {room}");
+            // application level heartbeat, for clients whose WebSocket pings don't get through
+            "/ping" => {
+                let _ = session.text(json!({ "type": "pong" }).to_string()).await;
+            }
 
-                    chat_server.join_room(conn, room).await;
+            "/pong" => {}
+
+            "/watch" => {
+                log::info!("conn {conn} watching the room directory");
+
+                chat_server.watch_directory(conn, true).await;
+
+                let _ = session.text("watching the room directory").await;
+            }
+
+            "/unwatch" => {
+                chat_server.watch_directory(conn, false).await;
+
+                let _ = session.text("stopped watching the room directory").await;
+            }
 
-                    session.text(format!("joined {room}")).await.unwrap();
-                }
-                None => {
-                    session.text("!!! room name is required").await.unwrap();
-                }
-            },
             _ => {
                 session
                     .text(format!("!!! unknown command: {msg}"))
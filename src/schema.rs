@@ -1,5 +1,17 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_events (id) {
+        id -> Text,
+        actor_id -> Nullable<Text>,
+        action -> Text,
+        room_id -> Nullable<Text>,
+        target_user_id -> Nullable<Text>,
+        details -> Text,
+        created_at -> Text,
+    }
+}
+
 diesel::table! {
     conversations (id) {
         id -> Text,
@@ -10,6 +22,98 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    failed_logins (id) {
+        id -> Text,
+        username -> Text,
+        user_id -> Nullable<Text>,
+        ip -> Text,
+        reason -> Text,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    filter_decisions (id) {
+        id -> Text,
+        room_id -> Text,
+        user_id -> Text,
+        conversation_id -> Nullable<Text>,
+        kind -> Text,
+        action -> Text,
+        message -> Text,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Text,
+        user_id -> Text,
+        token_hash -> Text,
+        expires_at -> Text,
+        used_at -> Nullable<Text>,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Text,
+        user_id -> Text,
+        code_hash -> Text,
+        used_at -> Nullable<Text>,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    reports (id) {
+        id -> Text,
+        room_id -> Text,
+        reporter_id -> Text,
+        reported_user_id -> Text,
+        conversation_id -> Nullable<Text>,
+        reason -> Text,
+        status -> Text,
+        resolution -> Nullable<Text>,
+        resolved_by -> Nullable<Text>,
+        resolved_at -> Nullable<Text>,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    room_bans (room_id, user_id) {
+        room_id -> Text,
+        user_id -> Text,
+        banned_by -> Text,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    room_events (room_id, seq) {
+        room_id -> Text,
+        seq -> BigInt,
+        sender_id -> Nullable<Text>,
+        payload -> Text,
+        created_at -> Text,
+        conversation_id -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    room_filters (id) {
+        id -> Text,
+        room_id -> Text,
+        kind -> Text,
+        value -> Text,
+        action -> Text,
+        created_at -> Text,
+    }
+}
+
 diesel::table! {
     rooms (id) {
         id -> Text,
@@ -17,6 +121,8 @@ diesel::table! {
         last_message -> Text,
         created_at -> Text,
         owner_id -> Text,
+        slow_mode_seconds -> Integer,
+        archived_at -> Nullable<Text>,
     }
 }
 
@@ -24,6 +130,15 @@ diesel::table! {
     rooms_users (room_id, user_id) {
         room_id -> Text,
         user_id -> Text,
+        role -> Text,
+    }
+}
+
+diesel::table! {
+    user_blocks (blocker_id, blocked_id) {
+        blocker_id -> Text,
+        blocked_id -> Text,
+        created_at -> Text,
     }
 }
 
@@ -33,18 +148,49 @@ diesel::table! {
         username -> Text,
         password -> Text,
         created_at -> Text,
+        totp_secret -> Nullable<Text>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<BigInt>,
+        display_name -> Nullable<Text>,
+        avatar_url -> Nullable<Text>,
+        bio -> Nullable<Text>,
+        status_message -> Nullable<Text>,
+        is_admin -> Bool,
+        suspended_at -> Nullable<Text>,
+        suspended_reason -> Nullable<Text>,
     }
 }
 
 diesel::joinable!(conversations -> rooms (room_id));
 diesel::joinable!(conversations -> users (user_id));
+diesel::joinable!(failed_logins -> users (user_id));
+diesel::joinable!(filter_decisions -> conversations (conversation_id));
+diesel::joinable!(filter_decisions -> rooms (room_id));
+diesel::joinable!(filter_decisions -> users (user_id));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(reports -> conversations (conversation_id));
+diesel::joinable!(reports -> rooms (room_id));
+diesel::joinable!(room_bans -> rooms (room_id));
+diesel::joinable!(room_events -> rooms (room_id));
+diesel::joinable!(room_filters -> rooms (room_id));
 diesel::joinable!(rooms -> users (owner_id));
 diesel::joinable!(rooms_users -> rooms (room_id));
 diesel::joinable!(rooms_users -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_events,
     conversations,
+    failed_logins,
+    filter_decisions,
+    password_reset_tokens,
+    recovery_codes,
+    reports,
+    room_bans,
+    room_events,
+    room_filters,
     rooms,
     rooms_users,
+    user_blocks,
     users,
 );
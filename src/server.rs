@@ -1,43 +1,172 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
     io,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
+    time::Duration,
 };
 
 use actix_web::web;
+use actix_ws::{CloseCode, CloseReason};
+use diesel::SqliteConnection;
+use futures_util::future::join_all;
 use rand::{thread_rng, Rng as _};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, oneshot};
+use serde_json::{json, Value};
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc, oneshot},
+    time::sleep,
+};
+
+use crate::{
+    backplane::{Backplane, Envelope, Event, NodeId},
+    db::{self, DbError},
+    models::Conversation,
+    types::DbPool,
+    ConnId, Msg, RoomId, UserId,
+};
+
+mod outbox;
+
+pub use outbox::{channel as outbox, Outbox, OutboxReceiver, OverflowPolicy, PushError};
+
+/// How long to wait before loading the rooms and blocks again after a failure.
+const INIT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Clients told to reconnect after a restart pick a delay up to this long, so they don't all
+/// come back at once.
+const RECONNECT_SPREAD: Duration = Duration::from_secs(5);
 
-use crate::{db, types::DbPool, ConnId, Msg, RoomId, UserId};
+/// How often to check whether every connection closed during a shutdown.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Times to try writing a room event to the log, as other writers may hold the database lock.
+const SEQUENCE_ATTEMPTS: u32 = 3;
+
+const SEQUENCE_RETRY_INTERVAL: Duration = Duration::from_millis(20);
 
 // type ListRoom = Vec<>
 #[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct WsRoom {
+pub struct WsRoom {
     room_id: String,
     users: HashSet<(ConnId, UserId)>,
 }
 
+/// A live WebSocket connection, as listed to site admins.
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+    /// A string, like in the `init` frame, as ids don't fit in a JavaScript number.
+    pub conn_id: String,
+    pub user_id: UserId,
+    pub rooms: Vec<RoomId>,
+}
+
+/// Live numbers from the chat server.
+#[derive(Debug, Default, Serialize)]
+pub struct ServerStats {
+    /// Open WebSocket connections.
+    pub connections: usize,
+    /// Distinct users with at least one open connection.
+    pub online_users: usize,
+    /// Rooms with at least one connection in them.
+    pub active_rooms: usize,
+    /// Connections established since the server started.
+    pub total_connections: usize,
+    /// Frames dropped because a connection's queue was full or already closed.
+    pub dropped_frames: u64,
+    /// Connections closed because their queue overflowed.
+    pub slow_consumer_disconnects: u64,
+    /// Connections closed since the server started, by why they closed.
+    pub closes: BTreeMap<CloseCause, u64>,
+}
+
+/// Why a WebSocket connection ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseCause {
+    /// The client sent a close frame.
+    Client,
+    /// The connection dropped without a close frame, or writing to it failed.
+    Lost,
+    /// The client didn't answer pings for longer than the client timeout.
+    Timeout,
+    /// The client sent a malformed or oversized frame.
+    Protocol,
+    /// The server closed it, e.g. because the user was suspended or couldn't keep up.
+    Server,
+    /// The connection couldn't be set up.
+    Error,
+}
+
+impl CloseCause {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloseCause::Client => "client",
+            CloseCause::Lost => "lost",
+            CloseCause::Timeout => "timeout",
+            CloseCause::Protocol => "protocol",
+            CloseCause::Server => "server",
+            CloseCause::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for CloseCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 // A command received by the ChatServer
 #[derive(Debug)]
 enum Command {
-    Connect {
-        conn_tx: mpsc::UnboundedSender<Msg>,
-        res_tx: oneshot::Sender<ConnId>,
-        user_id: String,
+    /// Add a connection, in the given rooms of this shard. Replies whether the id was free.
+    Register {
+        conn: ConnId,
+        conn_tx: Outbox,
+        user_id: UserId,
+        rooms: Vec<RoomId>,
+        res_tx: oneshot::Sender<bool>,
+    },
+
+    /// The rooms and blocks were loaded at startup. Sent by the server to itself.
+    Loaded {
+        rooms: Vec<RoomId>,
+        blocks: Vec<(UserId, UserId)>,
     },
 
     Disconnect {
         conn: ConnId,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    DisconnectUser {
+        user_id: UserId,
+        res_tx: oneshot::Sender<()>,
     },
 
     List {
         res_tx: oneshot::Sender<Vec<WsRoom>>,
     },
 
+    ListConnections {
+        res_tx: oneshot::Sender<Vec<ConnectionInfo>>,
+    },
+
+    /// The user a connection belongs to.
+    ConnectionUser {
+        conn: ConnId,
+        res_tx: oneshot::Sender<Option<UserId>>,
+    },
+
+    Stats {
+        res_tx: oneshot::Sender<ServerStats>,
+    },
+
     // TODO
     // CreateRoom {
     //     conn: ConnId,
@@ -50,15 +179,44 @@ enum Command {
     //     room: RoomId,
     //     res_tx: oneshot::Sender<()>,
     // },
-    Join {
+    Exit {
         conn: ConnId,
         room: RoomId,
         res_tx: oneshot::Sender<()>,
     },
 
-    Exit {
+    /// Send a message to the connections in a room and the ones watching the room directory.
+    Announce {
+        room_id: RoomId,
+        msg: Msg,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    /// Like [`Announce`](Command::Announce), then forget the room, which was deleted.
+    CloseRoom {
+        room_id: RoomId,
+        msg: Msg,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    /// Start or stop sending a connection the changes to the room directory.
+    Watch {
         conn: ConnId,
+        watch: bool,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    /// Add every connection of a user to a room they became a member of.
+    AddMember {
         room: RoomId,
+        user_id: UserId,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    /// Take every connection of a user out of a room they are no longer a member of.
+    RemoveMember {
+        room: RoomId,
+        user_id: UserId,
         res_tx: oneshot::Sender<()>,
     },
 
@@ -66,6 +224,20 @@ enum Command {
         msg: Msg,
         conn: ConnId,
         room_id: RoomId,
+        /// The user who wrote the message, if it is a user's message.
+        sender: Option<UserId>,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    Block {
+        blocker: UserId,
+        blocked: UserId,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    Unblock {
+        blocker: UserId,
+        blocked: UserId,
         res_tx: oneshot::Sender<()>,
     },
 
@@ -74,78 +246,238 @@ enum Command {
         conn: ConnId,
         res_tx: oneshot::Sender<()>,
     },
+
+    /// The connections in any of the given rooms of this shard.
+    RoomConnections {
+        rooms: Vec<RoomId>,
+        res_tx: oneshot::Sender<HashSet<ConnId>>,
+    },
+
+    /// Send a message to the given connections.
+    Deliver {
+        msg: Msg,
+        conn_ids: Vec<ConnId>,
+        res_tx: oneshot::Sender<()>,
+    },
+
+    /// Tell every connection, and every one registered from now on, that the server is
+    /// restarting, and close them.
+    Restart { res_tx: oneshot::Sender<()> },
 }
 
+/// One shard of the chat server.
+///
+/// Rooms are split across shards by a hash of their id, see [`shard_for`]. Every shard knows
+/// every connection and every block, so any shard can reach any connection, but each only routes
+/// the messages of its own rooms.
 #[derive(Debug)]
 pub struct ChatServer {
-    /// Map of connection IDs to their message receivers.
-    sessions: HashMap<ConnId, (mpsc::UnboundedSender<Msg>, UserId)>,
+    /// This shard's index.
+    shard: usize,
+
+    /// Number of shards.
+    shards: usize,
 
-    /// Map of room name to participant IDs in that room.
+    /// Map of connection IDs to their outgoing queues.
+    sessions: HashMap<ConnId, (Outbox, UserId)>,
+
+    /// Map of room name to participant IDs in that room, for the rooms of this shard.
     rooms: HashMap<RoomId, HashSet<ConnId>>,
 
-    /// Tracks total number of historical connections established.
-    visitor_count: Arc<AtomicUsize>,
+    /// Connections watching the room directory. They also get the events of rooms they aren't
+    /// in that change the room list, like rooms being created, joined or deleted.
+    watchers: HashSet<ConnId>,
+
+    /// Map of user IDs to the users they blocked.
+    blocks: HashMap<UserId, HashSet<UserId>>,
+
+    /// Frames dropped because a connection's queue was full or already closed.
+    dropped_frames: u64,
+
+    /// Connections closed because their queue overflowed.
+    slow_consumer_disconnects: u64,
+
+    /// Set once the server is shutting down.
+    restarting: bool,
 
     /// Command receiver.
     cmd_rx: mpsc::UnboundedReceiver<Command>,
 
+    /// Sender for the commands the server sends itself when background work finishes. Weak, so
+    /// the server still stops once every handle is dropped.
+    cmd_tx: mpsc::WeakUnboundedSender<Command>,
+
     pool: DbPool,
 }
 
 impl ChatServer {
-    pub fn new(pool: DbPool) -> (Self, ChatServerHandle) {
-        let mut rooms = HashMap::new();
-
-        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    /// Create `shards` shards (at least one), each to be driven by its own [`run`](Self::run)
+    /// task, and the handle that routes commands to them and publishes events to `backplane`.
+    ///
+    /// The last `event_retention` events of every room are kept for clients that resume.
+    pub fn new(
+        pool: DbPool,
+        shards: usize,
+        event_retention: i64,
+        backplane: Arc<dyn Backplane>,
+    ) -> (Vec<Self>, ChatServerHandle) {
+        let shards = shards.max(1);
+        let mut servers = Vec::with_capacity(shards);
+        let mut senders = Vec::with_capacity(shards);
+
+        for shard in 0..shards {
+            let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+
+            servers.push(Self {
+                shard,
+                shards,
+                sessions: HashMap::new(),
+                rooms: HashMap::new(),
+                watchers: HashSet::new(),
+                blocks: HashMap::new(),
+                dropped_frames: 0,
+                slow_consumer_disconnects: 0,
+                restarting: false,
+                cmd_rx,
+                cmd_tx: cmd_tx.downgrade(),
+                pool: pool.clone(),
+            });
+            senders.push(cmd_tx);
+        }
 
         (
-            Self {
-                sessions: HashMap::new(),
-                rooms,
+            servers,
+            ChatServerHandle {
+                shards: senders.into(),
                 visitor_count: Arc::new(AtomicUsize::new(0)),
-                cmd_rx,
+                closes: Arc::default(),
+                shutting_down: Arc::default(),
                 pool,
+                event_retention,
+                node: thread_rng().gen(),
+                backplane,
             },
-            ChatServerHandle { cmd_tx },
         )
     }
 
-    async fn broadcast(&self, conn: ConnId, msg: impl Into<Msg>) {
-        let msg = msg.into();
+    /// Queue a message for each of the given connections.
+    ///
+    /// Connections whose queue overflows under [`OverflowPolicy::DropConnection`] are closed.
+    /// Frames for connections whose queue is already closed count as dropped.
+    fn deliver(&mut self, conn_ids: &[ConnId], msg: Msg) {
+        let mut slow = Vec::new();
 
-        for (conn_id, (tx, _)) in &self.sessions {
-            if *conn_id == conn {
+        for conn_id in conn_ids {
+            let Some((outbox, _)) = self.sessions.get(conn_id) else {
                 continue;
+            };
+
+            match outbox.push(msg.clone()) {
+                Ok(dropped) => self.dropped_frames += dropped as u64,
+                Err(PushError::Overflow) => slow.push(*conn_id),
+                Err(PushError::Closed) => self.dropped_frames += 1,
             }
-            tx.send(msg.clone());
+        }
+
+        for conn_id in slow {
+            log::warn!("conn {conn_id}: outgoing queue is full; disconnecting slow consumer");
+
+            self.dropped_frames += 1;
+            self.slow_consumer_disconnects += 1;
+            self.close_connection(
+                conn_id,
+                CloseReason {
+                    code: CloseCode::Again,
+                    description: Some("slow consumer".to_string()),
+                },
+            );
         }
     }
 
+    /// Remove a connection and close its socket with `reason`.
+    fn close_connection(&mut self, conn_id: ConnId, reason: CloseReason) {
+        if let Some((outbox, _)) = self.sessions.remove(&conn_id) {
+            outbox.close(Some(reason));
+        }
+
+        self.watchers.remove(&conn_id);
+        for sessions in self.rooms.values_mut() {
+            sessions.remove(&conn_id);
+        }
+    }
+
+    async fn broadcast(&mut self, conn: ConnId, msg: impl Into<Msg>) {
+        let conn_ids: Vec<ConnId> = self
+            .sessions
+            .keys()
+            .copied()
+            .filter(|conn_id| *conn_id != conn)
+            .collect();
+
+        self.deliver(&conn_ids, msg.into());
+    }
+
     /// Send message to users in a room.
     ///
     /// `skip` is used to prevent messages triggered by a connection also being received by it.
-    async fn send_system_message(&self, room: &str, skip: ConnId, msg: impl Into<Msg>) {
-        if let Some(sessions) = self.rooms.get(room) {
-            let msg = msg.into();
+    /// Messages from a `sender` are not sent to users who blocked them.
+    async fn send_system_message(
+        &mut self,
+        room: &str,
+        skip: ConnId,
+        sender: Option<&str>,
+        msg: impl Into<Msg>,
+    ) {
+        let Some(sessions) = self.rooms.get(room) else {
+            return;
+        };
+
+        let conn_ids: Vec<ConnId> = sessions
+            .iter()
+            .copied()
+            .filter(|conn_id| *conn_id != skip)
+            .filter(|conn_id| match (self.sessions.get(conn_id), sender) {
+                (Some((_, user_id)), Some(sender)) => !self.has_blocked(user_id, sender),
+                (Some(_), None) => true,
+                (None, _) => false,
+            })
+            .collect();
 
-            for conn_id in sessions {
-                if *conn_id == skip {
-                    continue;
-                }
-                println!("send message {msg} to session:{}", conn_id);
-                if let Some((tx, _)) = self.sessions.get(conn_id) {
-                    tx.send(msg.clone());
-                }
-            }
+        self.deliver(&conn_ids, msg.into());
+    }
+
+    /// Send a message to the connections in the room and the ones watching the room directory.
+    /// Connections that are both get it once.
+    fn announce(&mut self, room: &str, msg: Msg) {
+        let mut conn_ids = self.watchers.clone();
+        if let Some(sessions) = self.rooms.get(room) {
+            conn_ids.extend(sessions);
         }
+
+        self.deliver(&conn_ids.into_iter().collect::<Vec<_>>(), msg);
+    }
+
+    /// Connections in any of the given rooms.
+    fn room_connections(&self, rooms: &[RoomId]) -> HashSet<ConnId> {
+        rooms
+            .iter()
+            .filter_map(|room| self.rooms.get(room))
+            .flatten()
+            .copied()
+            .collect()
     }
 
     /// Send message to all other users in current room.
     ///
     /// `conn` is used to find current room and prevent messages sent by a connection also being
     /// received by it.
-    async fn send_mesage(&self, conn: ConnId, room_id: RoomId, msg: impl Into<Msg>) {
+    async fn send_mesage(
+        &mut self,
+        conn: ConnId,
+        room_id: RoomId,
+        sender: Option<&str>,
+        msg: impl Into<Msg>,
+    ) {
         // if let Some(room) = self
         //     .rooms
         //     .iter()
@@ -153,34 +485,56 @@ impl ChatServer {
         // {
         //     self.send_system_message(room, conn, msg).await;
         // }
-        self.send_system_message(&room_id, conn, msg).await;
+        self.send_system_message(&room_id, conn, sender, msg).await;
     }
 
-    /// Register new session and assign unique ID to this session
-    async fn connect(&mut self, tx: mpsc::UnboundedSender<Msg>, user_id: UserId) -> ConnId {
-        // register session with random connection ID
-        let id = thread_rng().gen::<ConnId>();
-        self.sessions.insert(id, (tx, user_id.clone()));
+    fn has_blocked(&self, blocker: &str, blocked: &str) -> bool {
+        self.blocks
+            .get(blocker)
+            .is_some_and(|blocked_ids| blocked_ids.contains(blocked))
+    }
 
-        let pool = self.pool.clone();
+    fn block(&mut self, blocker: UserId, blocked: UserId) {
+        self.blocks.entry(blocker).or_default().insert(blocked);
+    }
 
-        // TODO: 1.join all rooms joined by the user
-        // 2. broadcast to all rooms
+    fn unblock(&mut self, blocker: &str, blocked: &str) {
+        if let Some(blocked_ids) = self.blocks.get_mut(blocker) {
+            blocked_ids.remove(blocked);
 
-        // join all rooms joined by the user
-        let joined_rooms = web::block(move || {
-            let mut conn = pool.get()?;
-            db::rooms::get_user_joined_rooms(&mut conn, user_id)
-        })
-        .await
-        .unwrap()
-        .unwrap();
+            if blocked_ids.is_empty() {
+                self.blocks.remove(blocker);
+            }
+        }
+    }
 
-        for room in joined_rooms {
-            self.rooms.entry(room.id).or_default().insert(id);
+    /// Register a session under the ID the handle picked for it.
+    fn register(&mut self, conn: ConnId, tx: Outbox, user_id: UserId, rooms: Vec<RoomId>) -> bool {
+        if self.sessions.contains_key(&conn) {
+            return false;
+        }
+
+        // a connection that made it in during a shutdown is sent away right away
+        if self.restarting {
+            send_restart(&tx);
+        }
+
+        self.sessions.insert(conn, (tx, user_id));
+
+        for room in rooms {
+            self.rooms.entry(room).or_default().insert(conn);
         }
 
-        id
+        true
+    }
+
+    /// Send every connection away, including the ones registered from now on.
+    fn restart(&mut self) {
+        self.restarting = true;
+
+        for (outbox, _) in self.sessions.values() {
+            send_restart(outbox);
+        }
     }
 
     /// Unregister connection from room map and broadcast disconnection message.
@@ -188,6 +542,7 @@ impl ChatServer {
         let mut rooms: Vec<RoomId> = Vec::new();
 
         if self.sessions.remove(&conn_id).is_some() {
+            self.watchers.remove(&conn_id);
             for (room_id, sessions) in &mut self.rooms {
                 if sessions.remove(&conn_id) {
                     rooms.push(room_id.to_owned());
@@ -196,11 +551,32 @@ impl ChatServer {
         }
 
         for room in rooms {
-            self.send_system_message(&room, 0, "Someone disconnected")
+            self.send_system_message(&room, 0, None, "Someone disconnected")
                 .await;
         }
     }
 
+    /// Drop every connection of a user.
+    ///
+    /// Dropping the outboxes ends the connections' message streams, which closes their sockets.
+    async fn disconnect_user(&mut self, user_id: &str) {
+        let conn_ids: Vec<ConnId> = self
+            .sessions
+            .iter()
+            .filter(|(_, (_, uid))| uid == user_id)
+            .map(|(conn_id, _)| *conn_id)
+            .collect();
+
+        for conn_id in conn_ids {
+            self.sessions.remove(&conn_id);
+            self.watchers.remove(&conn_id);
+
+            for sessions in self.rooms.values_mut() {
+                sessions.remove(&conn_id);
+            }
+        }
+    }
+
     fn list_rooms(&mut self) -> Vec<WsRoom> {
         self.rooms
             .iter()
@@ -222,81 +598,281 @@ impl ChatServer {
             .collect()
     }
 
-    /// Join room, send join message to new room.
-    async fn join_room(&mut self, conn_id: ConnId, room: RoomId) {
-        // send message to other users
-        self.rooms.entry(room.clone()).or_default().insert(conn_id);
+    fn list_connections(&self) -> Vec<ConnectionInfo> {
+        self.sessions
+            .iter()
+            .map(|(conn_id, (_, user_id))| ConnectionInfo {
+                conn_id: conn_id.to_string(),
+                user_id: user_id.clone(),
+                rooms: self
+                    .rooms
+                    .iter()
+                    .filter(|(_, conn_ids)| conn_ids.contains(conn_id))
+                    .map(|(room_id, _)| room_id.clone())
+                    .collect(),
+            })
+            .collect()
+    }
 
-        self.send_system_message(&room, conn_id, "Someone connected")
-            .await;
+    fn stats(&self) -> ServerStats {
+        ServerStats {
+            connections: self.sessions.len(),
+            online_users: self
+                .sessions
+                .values()
+                .map(|(_, user_id)| user_id)
+                .collect::<HashSet<_>>()
+                .len(),
+            active_rooms: self
+                .rooms
+                .values()
+                .filter(|conn_ids| !conn_ids.is_empty())
+                .count(),
+            // counted by the handle
+            total_connections: 0,
+            dropped_frames: self.dropped_frames,
+            slow_consumer_disconnects: self.slow_consumer_disconnects,
+            closes: BTreeMap::new(),
+        }
     }
 
     async fn exit_room(&mut self, conn_id: ConnId, room: RoomId) {
-        for (room_id, sessions) in &mut self.rooms {
+        if let Some(sessions) = self.rooms.get_mut(&room) {
             sessions.remove(&conn_id);
         }
     }
 
-    async fn init(&mut self) {
+    /// Put every connection of the user in the room, without telling the room; the caller
+    /// announces the new member.
+    fn add_member(&mut self, room: RoomId, user_id: &str) {
+        let conn_ids = self
+            .sessions
+            .iter()
+            .filter(|(_, (_, uid))| uid == user_id)
+            .map(|(conn_id, _)| *conn_id);
+
+        self.rooms.entry(room).or_default().extend(conn_ids);
+    }
+
+    /// Take every connection of the user out of the room.
+    fn remove_member(&mut self, room: &str, user_id: &str) {
+        let Some(conn_ids) = self.rooms.get_mut(room) else {
+            return;
+        };
+
+        conn_ids.retain(|conn_id| {
+            self.sessions
+                .get(conn_id)
+                .is_none_or(|(_, uid)| uid != user_id)
+        });
+    }
+
+    /// Load every room and block in the background, retrying until it works. They come back
+    /// as [`Command::Loaded`].
+    fn init(&self) {
         let pool = self.pool.clone();
-        let rooms = web::block(move || {
-            let mut conn = pool.get()?;
-            db::rooms::get_all_rooms(&mut conn)
-        })
-        .await
-        .unwrap()
-        .unwrap();
+        let cmd_tx = self.cmd_tx.clone();
 
-        for room in rooms {
-            let room_id = room.room.id;
-            self.rooms.insert(room_id, HashSet::new());
+        tokio::spawn(async move {
+            loop {
+                let res = query(pool.clone(), |conn| {
+                    let rooms = db::rooms::get_all_rooms(conn, false)?;
+                    let blocks = db::blocks::all_blocks(conn)?;
+
+                    Ok((rooms, blocks))
+                })
+                .await;
+
+                match res {
+                    Ok((rooms, blocks)) => {
+                        if let Some(cmd_tx) = cmd_tx.upgrade() {
+                            let _ = cmd_tx.send(Command::Loaded {
+                                rooms: rooms.into_iter().map(|room| room.room.id).collect(),
+                                blocks,
+                            });
+                        }
+                        return;
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "failed to load rooms and blocks, retrying in {INIT_RETRY_INTERVAL:?}: {err}"
+                        );
+                        sleep(INIT_RETRY_INTERVAL).await;
+                    }
+                }
+            }
+        });
+    }
+
+    fn loaded(&mut self, rooms: Vec<RoomId>, blocks: Vec<(UserId, UserId)>) {
+        for room_id in rooms {
+            if shard_for(&room_id, self.shards) == self.shard {
+                self.rooms.entry(room_id).or_default();
+            }
+        }
+
+        for (blocker, blocked) in blocks {
+            self.block(blocker, blocked);
         }
     }
 
+    /// Handle commands until every handle is dropped.
+    ///
+    /// This is the only task that routes messages, so handling a command must never wait on the
+    /// database or panic: queries run in the background and report back with an internal command.
     pub async fn run(mut self) -> io::Result<()> {
-        self.init().await;
+        self.init();
 
         while let Some(cmd) = self.cmd_rx.recv().await {
             match cmd {
-                Command::Connect {
+                Command::Register {
+                    conn,
                     conn_tx,
-                    res_tx,
                     user_id,
+                    rooms,
+                    res_tx,
                 } => {
-                    let conn_id = self.connect(conn_tx, user_id).await;
-                    res_tx.send(conn_id);
+                    let _ = res_tx.send(self.register(conn, conn_tx, user_id, rooms));
+                }
+
+                Command::Loaded { rooms, blocks } => {
+                    self.loaded(rooms, blocks);
                 }
-                Command::Disconnect { conn } => {
+
+                Command::Disconnect { conn, res_tx } => {
                     self.disconnect(conn).await;
+                    let _ = res_tx.send(());
+                }
+
+                Command::DisconnectUser { user_id, res_tx } => {
+                    self.disconnect_user(&user_id).await;
+                    let _ = res_tx.send(());
                 }
 
                 Command::List { res_tx } => {
-                    res_tx.send(self.list_rooms());
+                    let _ = res_tx.send(self.list_rooms());
                 }
 
-                Command::Join { conn, room, res_tx } => {
-                    self.join_room(conn, room).await;
-                    res_tx.send(());
+                Command::ListConnections { res_tx } => {
+                    let _ = res_tx.send(self.list_connections());
+                }
+
+                Command::ConnectionUser { conn, res_tx } => {
+                    let _ =
+                        res_tx.send(self.sessions.get(&conn).map(|(_, user_id)| user_id.clone()));
+                }
+
+                Command::Stats { res_tx } => {
+                    let _ = res_tx.send(self.stats());
                 }
 
                 Command::Exit { conn, room, res_tx } => {
                     self.exit_room(conn, room).await;
-                    res_tx.send(());
+                    let _ = res_tx.send(());
+                }
+
+                Command::Announce {
+                    room_id,
+                    msg,
+                    res_tx,
+                } => {
+                    self.announce(&room_id, msg);
+                    let _ = res_tx.send(());
+                }
+
+                Command::CloseRoom {
+                    room_id,
+                    msg,
+                    res_tx,
+                } => {
+                    self.announce(&room_id, msg);
+                    self.rooms.remove(&room_id);
+                    let _ = res_tx.send(());
+                }
+
+                Command::Watch {
+                    conn,
+                    watch,
+                    res_tx,
+                } => {
+                    // connections that left meanwhile are not watching anything
+                    if watch && self.sessions.contains_key(&conn) {
+                        self.watchers.insert(conn);
+                    } else {
+                        self.watchers.remove(&conn);
+                    }
+                    let _ = res_tx.send(());
+                }
+
+                Command::AddMember {
+                    room,
+                    user_id,
+                    res_tx,
+                } => {
+                    self.add_member(room, &user_id);
+                    let _ = res_tx.send(());
+                }
+
+                Command::RemoveMember {
+                    room,
+                    user_id,
+                    res_tx,
+                } => {
+                    self.remove_member(&room, &user_id);
+                    let _ = res_tx.send(());
                 }
 
                 Command::Message {
                     msg,
                     conn,
                     room_id,
+                    sender,
+                    res_tx,
+                } => {
+                    self.send_mesage(conn, room_id, sender.as_deref(), msg)
+                        .await;
+                    let _ = res_tx.send(());
+                }
+
+                Command::Block {
+                    blocker,
+                    blocked,
                     res_tx,
                 } => {
-                    self.send_mesage(conn, room_id, msg).await;
-                    res_tx.send(());
+                    self.block(blocker, blocked);
+                    let _ = res_tx.send(());
+                }
+
+                Command::Unblock {
+                    blocker,
+                    blocked,
+                    res_tx,
+                } => {
+                    self.unblock(&blocker, &blocked);
+                    let _ = res_tx.send(());
                 }
 
                 Command::Broadcast { msg, conn, res_tx } => {
                     self.broadcast(conn, msg).await;
-                    res_tx.send(());
+                    let _ = res_tx.send(());
+                }
+
+                Command::RoomConnections { rooms, res_tx } => {
+                    let _ = res_tx.send(self.room_connections(&rooms));
+                }
+
+                Command::Deliver {
+                    msg,
+                    conn_ids,
+                    res_tx,
+                } => {
+                    self.deliver(&conn_ids, msg);
+                    let _ = res_tx.send(());
+                }
+
+                Command::Restart { res_tx } => {
+                    self.restart();
+                    let _ = res_tx.send(());
                 }
             }
         }
@@ -305,89 +881,589 @@ impl ChatServer {
     }
 }
 
+/// Tell a connection the server is restarting and when to reconnect, then close it with
+/// code 1012 once the frames already queued for it are written.
+fn send_restart(outbox: &Outbox) {
+    let reconnect_after_ms = thread_rng().gen_range(0..=RECONNECT_SPREAD.as_millis() as u64);
+
+    let _ = outbox.push(
+        json!({
+            "type": "restarting",
+            "data": {
+                "message": "The server is restarting.",
+                "reconnect_after_ms": reconnect_after_ms,
+            }
+        })
+        .to_string()
+        .into(),
+    );
+    outbox.close_after_queued(CloseReason {
+        code: CloseCode::Restart,
+        description: Some("server restarting".to_string()),
+    });
+}
+
+/// Run a database query on the blocking thread pool.
+async fn query<T, F>(pool: DbPool, query: F) -> Result<T, DbError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut SqliteConnection) -> Result<T, DbError> + Send + 'static,
+{
+    web::block(move || {
+        let mut conn = pool.get()?;
+        query(&mut conn)
+    })
+    .await
+    .unwrap_or_else(|err| Err(err.into()))
+}
+
+/// The shard that owns a room.
+pub fn shard_for(room_id: &str, shards: usize) -> usize {
+    // `DefaultHasher::new` always uses the same keys, so a room stays on its shard
+    let mut hasher = DefaultHasher::new();
+    room_id.hash(&mut hasher);
+
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Routes commands to the shard owning the room, or to every shard for commands about
+/// connections and users.
+///
+/// Events that concern other server processes too are published to the backplane; the ones
+/// they publish are applied by [`relay`](Self::relay).
 #[derive(Debug, Clone)]
 pub struct ChatServerHandle {
-    cmd_tx: mpsc::UnboundedSender<Command>,
+    shards: Arc<[mpsc::UnboundedSender<Command>]>,
+
+    /// Tracks total number of historical connections established.
+    visitor_count: Arc<AtomicUsize>,
+
+    /// Connections closed in this process, by cause.
+    closes: Arc<Mutex<BTreeMap<CloseCause, u64>>>,
+
+    /// Set once this process is shutting down and takes no new connections.
+    shutting_down: Arc<AtomicBool>,
+
+    pool: DbPool,
+
+    /// Events kept per room in the event log.
+    event_retention: i64,
+
+    /// This process on the backplane.
+    node: NodeId,
+
+    backplane: Arc<dyn Backplane>,
 }
 
 impl ChatServerHandle {
-    pub async fn connect(&self, conn_tx: mpsc::UnboundedSender<Msg>, user_id: UserId) -> ConnId {
+    fn shard(&self, room_id: &str) -> usize {
+        shard_for(room_id, self.shards.len())
+    }
+
+    /// Send a command to one shard and wait for its reply, or `None` if the shard has stopped,
+    /// as happens at the end of a shutdown.
+    async fn try_ask<T>(
+        &self,
+        shard: usize,
+        cmd: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Option<T> {
         let (res_tx, res_rx) = oneshot::channel();
 
-        self.cmd_tx
-            .send(Command::Connect {
+        if self.shards[shard].send(cmd(res_tx)).is_err() {
+            log::error!("chat server shard {shard} has stopped");
+            return None;
+        }
+
+        match res_rx.await {
+            Ok(res) => Some(res),
+            Err(_) => {
+                log::error!("chat server shard {shard} stopped without replying");
+                None
+            }
+        }
+    }
+
+    /// Send a command to one shard and wait for its reply, falling back to the default reply
+    /// if the shard has stopped.
+    async fn ask<T: Default>(
+        &self,
+        shard: usize,
+        cmd: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> T {
+        self.try_ask(shard, cmd).await.unwrap_or_default()
+    }
+
+    /// Send a command to every shard and wait for all of their replies.
+    async fn ask_all<T: Default>(&self, cmd: impl Fn(oneshot::Sender<T>) -> Command) -> Vec<T> {
+        join_all((0..self.shards.len()).map(|shard| self.ask(shard, &cmd))).await
+    }
+
+    /// Register a connection with every shard. Fails if the user's rooms can't be loaded.
+    pub async fn connect(&self, conn_tx: Outbox, user_id: UserId) -> Result<ConnId, DbError> {
+        let query_user_id = user_id.clone();
+        let joined_rooms = query(self.pool.clone(), move |conn| {
+            db::rooms::get_user_joined_rooms(conn, query_user_id)
+        })
+        .await?;
+
+        let mut rooms = vec![Vec::new(); self.shards.len()];
+        for room in joined_rooms {
+            rooms[self.shard(&room.id)].push(room.id);
+        }
+
+        // the first shard picks the id, so it is unique among live connections; 0 means
+        // "no connection"
+        let conn = loop {
+            let conn = thread_rng().gen::<ConnId>();
+            if conn == 0 {
+                continue;
+            }
+
+            let registered = self
+                .try_ask(0, |res_tx| Command::Register {
+                    conn,
+                    conn_tx: conn_tx.clone(),
+                    user_id: user_id.clone(),
+                    rooms: rooms[0].clone(),
+                    res_tx,
+                })
+                .await
+                .ok_or("the chat server has stopped")?;
+            if registered {
+                break conn;
+            }
+        };
+
+        let others = rooms.into_iter().enumerate().skip(1).map(|(shard, rooms)| {
+            let conn_tx = conn_tx.clone();
+            let user_id = user_id.clone();
+
+            self.ask(shard, move |res_tx| Command::Register {
+                conn,
                 conn_tx,
-                res_tx,
                 user_id,
+                rooms,
+                res_tx,
             })
-            .unwrap();
+        });
+        join_all(others).await;
 
-        res_rx.await.unwrap()
+        self.visitor_count.fetch_add(1, Ordering::SeqCst);
+
+        Ok(conn)
     }
 
     pub async fn disconnect(&self, conn: ConnId) {
-        let (res_tx, res_rx) = oneshot::channel();
+        self.ask_all(|res_tx| Command::Disconnect { conn, res_tx })
+            .await;
+    }
 
-        self.cmd_tx.send(Command::Disconnect { conn }).unwrap();
+    /// Whether this process is shutting down, so new connections are turned away.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
 
-        res_rx.await.unwrap()
+    /// Stop taking connections and close the ones of this process with code 1012 (service
+    /// restart), after a `restarting` frame telling the client when to reconnect. Returns once
+    /// every connection is closed.
+    pub async fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        // every shard knows every connection, and the first one registers them first
+        self.ask(0, |res_tx| Command::Restart { res_tx }).await;
+
+        while self
+            .ask(0, |res_tx| Command::Stats { res_tx })
+            .await
+            .connections
+            > 0
+        {
+            sleep(SHUTDOWN_POLL_INTERVAL).await;
+        }
     }
 
-    pub async fn send_message(&self, msg: Msg, room_id: String, conn: ConnId) {
-        let (res_tx, res_rx) = oneshot::channel();
+    /// Count a closed connection for [`stats`](Self::stats).
+    pub fn count_close(&self, cause: CloseCause) {
+        let mut closes = self.closes.lock().unwrap_or_else(|err| err.into_inner());
+        *closes.entry(cause).or_default() += 1;
+    }
 
-        println!("send message: {msg}, {conn},{room_id}");
-        self.cmd_tx
-            .send(Command::Message {
-                msg,
-                conn,
+    /// Close every connection of a user, in every process.
+    pub async fn disconnect_user(&self, user_id: UserId) {
+        self.publish(Event::DisconnectUser { user_id }, 0).await
+    }
+
+    pub async fn send_message(&self, msg: impl Into<Msg>, room_id: String, conn: ConnId) {
+        let msg = self.sequence(&room_id, None, None, msg.into()).await;
+
+        self.publish(
+            Event::Message {
                 room_id,
-                res_tx,
+                sender: None,
+                msg,
+            },
+            conn,
+        )
+        .await
+    }
+
+    /// Send a user's message to its room like [`send_message`](Self::send_message), skipping
+    /// users who blocked the author.
+    pub async fn send_conversation(&self, conversation: &Conversation, conn: ConnId) {
+        let msg = json!({
+            "type": "message",
+            "data": conversation,
+        })
+        .to_string();
+        let msg = self
+            .sequence(
+                &conversation.room_id,
+                Some(&conversation.user_id),
+                Some(&conversation.id),
+                msg.into(),
+            )
+            .await;
+
+        self.publish(
+            Event::Message {
+                room_id: conversation.room_id.clone(),
+                sender: Some(conversation.user_id.clone()),
+                msg,
+            },
+            conn,
+        )
+        .await
+    }
+
+    /// Number a room event and append it to the room's log, so clients that reconnect can
+    /// replay it. The frame, a JSON object, gets the `room_id` and its `seq` added.
+    ///
+    /// If the log can't be written the frame is sent without a `seq`, and clients that miss it
+    /// won't get it back.
+    async fn sequence(
+        &self,
+        room_id: &str,
+        sender: Option<&str>,
+        conversation: Option<&str>,
+        msg: Msg,
+    ) -> Msg {
+        let Ok(Value::Object(frame)) = serde_json::from_str::<Value>(&msg) else {
+            log::warn!("not numbering a frame that isn't a JSON object: {msg}");
+            return msg;
+        };
+
+        for attempt in 1..=SEQUENCE_ATTEMPTS {
+            let mut frame = frame.clone();
+            let room = room_id.to_string();
+            let sender = sender.map(str::to_string);
+            let conversation = conversation.map(str::to_string);
+            let retention = self.event_retention;
+
+            let appended = query(self.pool.clone(), move |conn| {
+                db::room_events::append(
+                    conn,
+                    &room,
+                    sender.as_deref(),
+                    conversation.as_deref(),
+                    |seq| {
+                        frame.insert("room_id".to_string(), json!(room));
+                        frame.insert("seq".to_string(), json!(seq));
+                        Value::Object(frame).to_string()
+                    },
+                    retention,
+                )
             })
-            .unwrap();
+            .await;
+
+            match appended {
+                Ok((_, payload)) => return payload.into(),
+                Err(err) if attempt < SEQUENCE_ATTEMPTS => {
+                    log::debug!("retrying to log an event in room {room_id}: {err}");
+                    sleep(SEQUENCE_RETRY_INTERVAL).await;
+                }
+                Err(err) => log::error!("failed to log an event in room {room_id}: {err}"),
+            }
+        }
 
-        res_rx.await.unwrap()
+        msg
     }
 
-    pub async fn broadcast(&self, conn: ConnId, msg: Msg) {
-        let (res_tx, res_rx) = oneshot::channel();
+    /// Send an event that changes the room list, like a member joining, to the room and to the
+    /// connections watching the room directory, in every process. It is numbered and logged
+    /// like the room's messages.
+    pub async fn announce(&self, room_id: RoomId, msg: impl Into<Msg>) {
+        let msg = self.sequence(&room_id, None, None, msg.into()).await;
 
-        self.cmd_tx
-            .send(Command::Broadcast { msg, conn, res_tx })
-            .unwrap();
+        self.publish(Event::Announce { room_id, msg }, 0).await
+    }
 
-        res_rx.await.unwrap()
+    /// Announce that a room was deleted for good, then stop routing its messages.
+    pub async fn close_room(&self, room_id: RoomId, msg: impl Into<Msg>) {
+        self.publish(
+            Event::RoomClosed {
+                room_id,
+                msg: msg.into(),
+            },
+            0,
+        )
+        .await
     }
 
-    pub async fn list_rooms(&self) -> Vec<WsRoom> {
-        let (res_tx, res_rx) = oneshot::channel();
+    /// Start or stop sending a connection of this process the changes to the room directory.
+    pub async fn watch_directory(&self, conn: ConnId, watch: bool) {
+        self.ask_all(|res_tx| Command::Watch {
+            conn,
+            watch,
+            res_tx,
+        })
+        .await;
+    }
 
-        self.cmd_tx.send(Command::List { res_tx }).unwrap();
+    /// Put the live connections of a user who joined a room in the room, in every process.
+    pub async fn add_member(&self, room_id: RoomId, user_id: UserId) {
+        self.publish(Event::MemberAdded { room_id, user_id }, 0)
+            .await
+    }
 
-        res_rx.await.unwrap()
+    /// Take the live connections of a user who left a room out of the room, in every process.
+    pub async fn remove_member(&self, room_id: RoomId, user_id: UserId) {
+        self.publish(Event::MemberRemoved { room_id, user_id }, 0)
+            .await
     }
 
-    pub async fn join_room(&self, conn: ConnId, room: impl Into<RoomId>) {
-        let (res_tx, res_rx) = oneshot::channel();
+    pub async fn block(&self, blocker: UserId, blocked: UserId) {
+        self.publish(Event::Block { blocker, blocked }, 0).await
+    }
 
-        self.cmd_tx
-            .send(Command::Join {
-                conn,
-                room: room.into(),
-                res_tx,
-            })
-            .unwrap();
+    pub async fn unblock(&self, blocker: UserId, blocked: UserId) {
+        self.publish(Event::Unblock { blocker, blocked }, 0).await
+    }
 
-        res_rx.await.unwrap()
+    /// Send a message to every connection except `conn`.
+    pub async fn broadcast(&self, conn: ConnId, msg: impl Into<Msg>) {
+        self.publish(Event::Broadcast { msg: msg.into() }, conn)
+            .await
     }
 
-    pub async fn exit_room(&self, conn: ConnId, room: RoomId) {
-        let (res_tx, res_rx) = oneshot::channel();
+    /// Send a message to every connection in any of the given rooms.
+    ///
+    /// Connections that are in several of the rooms receive the message only once.
+    pub async fn send_rooms_message(&self, rooms: Vec<RoomId>, msg: impl Into<Msg>) {
+        self.publish(
+            Event::RoomsMessage {
+                rooms,
+                msg: msg.into(),
+            },
+            0,
+        )
+        .await
+    }
+
+    /// Publish an event to the other processes and apply it here. `conn` is the connection that
+    /// caused it, which doesn't receive its own message.
+    async fn publish(&self, event: Event, conn: ConnId) {
+        self.backplane.publish(Envelope {
+            node: self.node,
+            event: event.clone(),
+        });
+
+        self.apply(event, conn).await
+    }
+
+    /// Apply the events other processes publish on the backplane, until it closes.
+    pub async fn relay(self) -> io::Result<()> {
+        let mut events = self.backplane.subscribe();
+
+        loop {
+            match events.recv().await {
+                Ok(envelope) if envelope.node != self.node => self.apply(envelope.event, 0).await,
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("fell behind the backplane and missed {missed} events");
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    async fn apply(&self, event: Event, conn: ConnId) {
+        match event {
+            Event::Message {
+                room_id,
+                sender,
+                msg,
+            } => {
+                self.ask(self.shard(&room_id), |res_tx| Command::Message {
+                    msg,
+                    conn,
+                    room_id,
+                    sender,
+                    res_tx,
+                })
+                .await
+            }
+
+            Event::RoomsMessage { rooms, msg } => {
+                let mut by_shard = vec![Vec::new(); self.shards.len()];
+                for room in rooms {
+                    by_shard[self.shard(&room)].push(room);
+                }
+
+                let lookups = by_shard
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, rooms)| !rooms.is_empty())
+                    .map(|(shard, rooms)| {
+                        self.ask(shard, move |res_tx| Command::RoomConnections {
+                            rooms,
+                            res_tx,
+                        })
+                    });
+
+                let conn_ids: HashSet<ConnId> =
+                    join_all(lookups).await.into_iter().flatten().collect();
+
+                self.ask(0, |res_tx| Command::Deliver {
+                    msg,
+                    conn_ids: conn_ids.into_iter().collect(),
+                    res_tx,
+                })
+                .await
+            }
 
-        self.cmd_tx
-            .send(Command::Exit { conn, room, res_tx })
-            .unwrap();
+            // every shard knows every connection
+            Event::Broadcast { msg } => {
+                self.ask(0, |res_tx| Command::Broadcast { msg, conn, res_tx })
+                    .await
+            }
+
+            Event::Announce { room_id, msg } => {
+                self.ask(self.shard(&room_id), |res_tx| Command::Announce {
+                    room_id,
+                    msg,
+                    res_tx,
+                })
+                .await
+            }
+
+            Event::RoomClosed { room_id, msg } => {
+                self.ask(self.shard(&room_id), |res_tx| Command::CloseRoom {
+                    room_id,
+                    msg,
+                    res_tx,
+                })
+                .await
+            }
+
+            Event::MemberAdded { room_id, user_id } => {
+                self.ask(self.shard(&room_id), |res_tx| Command::AddMember {
+                    room: room_id,
+                    user_id,
+                    res_tx,
+                })
+                .await
+            }
 
-        res_rx.await.unwrap()
+            Event::MemberRemoved { room_id, user_id } => {
+                self.ask(self.shard(&room_id), |res_tx| Command::RemoveMember {
+                    room: room_id,
+                    user_id,
+                    res_tx,
+                })
+                .await
+            }
+
+            Event::Block { blocker, blocked } => {
+                self.ask_all(|res_tx| Command::Block {
+                    blocker: blocker.clone(),
+                    blocked: blocked.clone(),
+                    res_tx,
+                })
+                .await;
+            }
+
+            Event::Unblock { blocker, blocked } => {
+                self.ask_all(|res_tx| Command::Unblock {
+                    blocker: blocker.clone(),
+                    blocked: blocked.clone(),
+                    res_tx,
+                })
+                .await;
+            }
+
+            Event::DisconnectUser { user_id } => {
+                self.ask_all(|res_tx| Command::DisconnectUser {
+                    user_id: user_id.clone(),
+                    res_tx,
+                })
+                .await;
+            }
+        }
+    }
+
+    pub async fn list_rooms(&self) -> Vec<WsRoom> {
+        self.ask_all(|res_tx| Command::List { res_tx })
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    pub async fn list_connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections: HashMap<String, ConnectionInfo> = HashMap::new();
+
+        for connection in self
+            .ask_all(|res_tx| Command::ListConnections { res_tx })
+            .await
+            .into_iter()
+            .flatten()
+        {
+            match connections.get_mut(&connection.conn_id) {
+                Some(known) => known.rooms.extend(connection.rooms),
+                None => {
+                    connections.insert(connection.conn_id.clone(), connection);
+                }
+            }
+        }
+
+        connections.into_values().collect()
+    }
+
+    /// The user a connection of this process belongs to, if it is open.
+    pub async fn connection_user(&self, conn: ConnId) -> Option<UserId> {
+        // every shard knows every connection
+        self.ask(0, |res_tx| Command::ConnectionUser { conn, res_tx })
+            .await
+    }
+
+    pub async fn stats(&self) -> ServerStats {
+        let shards = self.ask_all(|res_tx| Command::Stats { res_tx }).await;
+
+        // connections are registered with every shard, so any shard can count them
+        ServerStats {
+            connections: shards[0].connections,
+            online_users: shards[0].online_users,
+            active_rooms: shards.iter().map(|stats| stats.active_rooms).sum(),
+            total_connections: self.visitor_count.load(Ordering::SeqCst),
+            dropped_frames: shards.iter().map(|stats| stats.dropped_frames).sum(),
+            slow_consumer_disconnects: shards
+                .iter()
+                .map(|stats| stats.slow_consumer_disconnects)
+                .sum(),
+            closes: self
+                .closes
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .clone(),
+        }
+    }
+
+    pub async fn exit_room(&self, conn: ConnId, room: RoomId) {
+        self.ask(self.shard(&room), |res_tx| Command::Exit {
+            conn,
+            room,
+            res_tx,
+        })
+        .await
     }
 }
@@ -0,0 +1,352 @@
+//! Bounded queues of frames waiting to be written to a WebSocket connection.
+//!
+//! The chat server pushes frames into a connection's [`Outbox`] and the connection's socket task
+//! takes them out with [`OutboxReceiver::recv`]. When a client reads slower than frames arrive,
+//! the queue fills up and its [`OverflowPolicy`] decides what gives.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use actix_ws::CloseReason;
+use serde_json::json;
+use tokio::sync::Notify;
+
+use crate::Msg;
+
+/// What to do with a frame that doesn't fit in a full queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued frame to make room.
+    DropOldest,
+    /// Refuse the frame; the server then disconnects the connection.
+    DropConnection,
+    /// Replace the queued frames with a single `lagged` frame saying how many were skipped, so
+    /// the client knows to refetch.
+    Coalesce,
+}
+
+impl OverflowPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OverflowPolicy::DropOldest => "drop_oldest",
+            OverflowPolicy::DropConnection => "drop_connection",
+            OverflowPolicy::Coalesce => "coalesce",
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop_connection" => Ok(OverflowPolicy::DropConnection),
+            "coalesce" => Ok(OverflowPolicy::Coalesce),
+            _ => Err(format!("unknown overflow policy {s:?}")),
+        }
+    }
+}
+
+/// Why [`Outbox::push`] refused a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushError {
+    /// The queue is full and the policy is [`DropConnection`](OverflowPolicy::DropConnection).
+    Overflow,
+    /// The queue is closed, so nothing will ever write the frame.
+    Closed,
+}
+
+#[derive(Debug)]
+struct State {
+    queue: VecDeque<Msg>,
+    /// Frames dropped by [`OverflowPolicy::Coalesce`] that the client hasn't been told about.
+    lagged: usize,
+    closed: bool,
+    close_reason: Option<CloseReason>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+    /// Number of [`Outbox`] clones alive.
+    senders: AtomicUsize,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl Shared {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // the lock is never held across anything that can panic
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn close(&self, reason: Option<CloseReason>) {
+        let mut state = self.state();
+        if !state.closed {
+            state.closed = true;
+            state.close_reason = reason;
+        }
+        drop(state);
+
+        self.notify.notify_one();
+    }
+}
+
+/// Create a queue holding up to `capacity` frames.
+pub fn channel(capacity: usize, policy: OverflowPolicy) -> (Outbox, OutboxReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity.min(64)),
+            lagged: 0,
+            closed: false,
+            close_reason: None,
+        }),
+        notify: Notify::new(),
+        senders: AtomicUsize::new(1),
+        capacity: capacity.max(1),
+        policy,
+    });
+
+    (
+        Outbox {
+            shared: shared.clone(),
+        },
+        OutboxReceiver { shared },
+    )
+}
+
+/// The sending half, owned by the chat server shards. Dropping the last clone closes the queue.
+#[derive(Debug)]
+pub struct Outbox {
+    shared: Arc<Shared>,
+}
+
+impl Outbox {
+    /// Queue a frame, returning how many frames the overflow policy dropped to fit it.
+    pub fn push(&self, msg: Msg) -> Result<usize, PushError> {
+        let mut state = self.shared.state();
+        if state.closed {
+            return Err(PushError::Closed);
+        }
+
+        let mut dropped = 0;
+        if state.queue.len() >= self.shared.capacity {
+            match self.shared.policy {
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    dropped = 1;
+                }
+                OverflowPolicy::DropConnection => return Err(PushError::Overflow),
+                OverflowPolicy::Coalesce => {
+                    dropped = state.queue.len();
+                    state.lagged += dropped;
+                    state.queue.clear();
+                }
+            }
+        }
+
+        state.queue.push_back(msg);
+        drop(state);
+
+        self.shared.notify.notify_one();
+
+        Ok(dropped)
+    }
+
+    /// Close the queue. The receiver yields the frames still queued, unless `reason` is given,
+    /// in which case they are discarded and the connection closes right away with `reason`.
+    pub fn close(&self, reason: Option<CloseReason>) {
+        if reason.is_some() {
+            let mut state = self.shared.state();
+            state.queue.clear();
+            state.lagged = 0;
+        }
+
+        self.shared.close(reason);
+    }
+
+    /// Close the queue once the frames already queued are written, then close the connection
+    /// with `reason`.
+    pub fn close_after_queued(&self, reason: CloseReason) {
+        self.shared.close(Some(reason));
+    }
+}
+
+impl Clone for Outbox {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.close(None);
+        }
+    }
+}
+
+/// The receiving half, owned by a connection's socket task.
+#[derive(Debug)]
+pub struct OutboxReceiver {
+    shared: Arc<Shared>,
+}
+
+impl OutboxReceiver {
+    /// Next frame to write, or `None` once the queue is closed and drained.
+    pub async fn recv(&self) -> Option<Msg> {
+        loop {
+            {
+                let mut state = self.shared.state();
+
+                if state.lagged > 0 {
+                    let dropped = std::mem::take(&mut state.lagged);
+                    return Some(
+                        json!({
+                            "type": "lagged",
+                            "data": {
+                                "dropped": dropped,
+                            }
+                        })
+                        .to_string()
+                        .into(),
+                    );
+                }
+
+                if let Some(msg) = state.queue.pop_front() {
+                    return Some(msg);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// Why the server closed the queue, if it gave a reason.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.shared.state().close_reason.clone()
+    }
+}
+
+impl Drop for OutboxReceiver {
+    fn drop(&mut self) {
+        self.shared.close(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_ws::CloseCode;
+
+    use super::*;
+
+    fn frame(n: usize) -> Msg {
+        n.to_string().into()
+    }
+
+    /// Everything the receiver yields until the queue is drained.
+    async fn drain(rx: &OutboxReceiver) -> Vec<Msg> {
+        let mut frames = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            frames.push(msg);
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_newest_frames() {
+        let (tx, rx) = channel(2, OverflowPolicy::DropOldest);
+
+        assert_eq!(tx.push(frame(1)), Ok(0));
+        assert_eq!(tx.push(frame(2)), Ok(0));
+        assert_eq!(tx.push(frame(3)), Ok(1));
+        drop(tx);
+
+        assert_eq!(drain(&rx).await, [frame(2), frame(3)]);
+    }
+
+    #[tokio::test]
+    async fn drop_connection_refuses_frames_when_full() {
+        let (tx, rx) = channel(2, OverflowPolicy::DropConnection);
+
+        assert_eq!(tx.push(frame(1)), Ok(0));
+        assert_eq!(tx.push(frame(2)), Ok(0));
+        assert_eq!(tx.push(frame(3)), Err(PushError::Overflow));
+        drop(tx);
+
+        assert_eq!(drain(&rx).await, [frame(1), frame(2)]);
+    }
+
+    #[tokio::test]
+    async fn coalesce_replaces_the_queue_with_a_lagged_frame() {
+        let (tx, rx) = channel(2, OverflowPolicy::Coalesce);
+
+        assert_eq!(tx.push(frame(1)), Ok(0));
+        assert_eq!(tx.push(frame(2)), Ok(0));
+        assert_eq!(tx.push(frame(3)), Ok(2));
+        drop(tx);
+
+        let lagged = json!({ "type": "lagged", "data": { "dropped": 2 } });
+        let frames = drain(&rx).await;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&frames[0]).unwrap(),
+            lagged
+        );
+        assert_eq!(frames[1], frame(3));
+    }
+
+    #[tokio::test]
+    async fn close_with_a_reason_discards_queued_frames() {
+        let (tx, rx) = channel(4, OverflowPolicy::DropOldest);
+
+        tx.push(frame(1)).unwrap();
+        tx.close(Some(CloseCode::Policy.into()));
+        assert_eq!(tx.push(frame(2)), Err(PushError::Closed));
+
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(
+            rx.close_reason().map(|reason| reason.code),
+            Some(CloseCode::Policy)
+        );
+    }
+
+    #[tokio::test]
+    async fn closes_when_the_last_sender_drops() {
+        let (tx, rx) = channel(4, OverflowPolicy::DropOldest);
+        let other = tx.clone();
+
+        tx.push(frame(1)).unwrap();
+        drop(tx);
+        other.push(frame(2)).unwrap();
+        assert_eq!(rx.recv().await, Some(frame(1)));
+        assert_eq!(rx.recv().await, Some(frame(2)));
+
+        drop(other);
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(rx.close_reason(), None);
+    }
+}
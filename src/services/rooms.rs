@@ -1,31 +1,100 @@
-use actix_web::{error::ErrorInternalServerError, web};
+use actix_web::web;
+use diesel::Connection;
+use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
     db::{self, DbError},
+    models::RoomResponse,
     types::DbPool,
 };
 
+/// Create a room with its creator as the first member.
+///
+/// Everything happens in one transaction, so a failure never leaves a room without its owner.
+pub async fn create_room(
+    pool: web::Data<DbPool>,
+    user_id: Uuid,
+    room_name: String,
+) -> Result<RoomResponse, DbError> {
+    web::block(move || {
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let room = db::rooms::create_room(conn, &user_id, &room_name)?;
+            db::audit::record(
+                conn,
+                Some(&user_id.to_string()),
+                "room.create",
+                Some(&room.id),
+                None,
+                json!({ "name": room.name }),
+            )?;
+
+            let room_id = Uuid::parse_str(&room.id)?;
+            db::rooms_users::join_room(conn, user_id, room_id)?;
+            record_membership(conn, "room.join", user_id, room_id)?;
+
+            db::rooms::get_room(conn, room_id)?.ok_or_else(|| "created room not found".into())
+        })
+    })
+    .await?
+}
+
+/// Returns `false` if the user already was a member.
 pub async fn join_room(
     pool: web::Data<DbPool>,
     user_id: Uuid,
     room_id: Uuid,
-) -> Result<(), DbError> {
+) -> Result<bool, DbError> {
     web::block(move || {
         let mut conn = pool.get()?;
-        db::rooms_users::join_room(&mut conn, user_id, room_id)
+        conn.transaction(|conn| {
+            if !db::rooms_users::join_room(conn, user_id, room_id)? {
+                return Ok(false);
+            }
+            record_membership(conn, "room.join", user_id, room_id)?;
+
+            Ok(true)
+        })
     })
     .await?
 }
 
+/// Returns `false` if the user wasn't a member.
 pub async fn exit_room(
     pool: web::Data<DbPool>,
     user_id: Uuid,
     room_id: Uuid,
-) -> Result<(), DbError> {
+) -> Result<bool, DbError> {
     web::block(move || {
         let mut conn = pool.get()?;
-        db::rooms_users::exit_room(&mut conn, user_id, room_id)
+        conn.transaction(|conn| {
+            if !db::rooms_users::exit_room(conn, user_id, room_id)? {
+                return Ok(false);
+            }
+            record_membership(conn, "room.exit", user_id, room_id)?;
+
+            Ok(true)
+        })
     })
     .await?
 }
+
+fn record_membership(
+    conn: &mut diesel::SqliteConnection,
+    action: &str,
+    user_id: Uuid,
+    room_id: Uuid,
+) -> Result<(), DbError> {
+    let user_id = user_id.to_string();
+    db::audit::record(
+        conn,
+        Some(&user_id),
+        action,
+        Some(&room_id.to_string()),
+        Some(&user_id),
+        json!({}),
+    )?;
+
+    Ok(())
+}
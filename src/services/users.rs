@@ -1,4 +1,4 @@
-use actix_web::{error::ErrorInternalServerError, web};
+use actix_web::web;
 use uuid::Uuid;
 
 use crate::{
@@ -1,4 +1,3 @@
-use actix_web::error::ErrorInternalServerError;
 use diesel::{
     r2d2::{self, ConnectionManager},
     SqliteConnection,
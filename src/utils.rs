@@ -1,32 +1,35 @@
 use actix_session::Session;
-use actix_web::{error, HttpRequest, HttpResponse};
-use serde_json::json;
+use actix_web::HttpRequest;
 use uuid::Uuid;
 
-use crate::ConnId;
+use crate::{error::ApiError, ConnId};
 
-pub fn get_user_id(session: &Session) -> Uuid {
-    session.get("user_id").unwrap().unwrap()
+/// The signed in user. Fails if the session has no user, which the authentication middleware
+/// normally rules out.
+pub fn get_user_id(session: &Session) -> Result<Uuid, ApiError> {
+    session
+        .get("user_id")?
+        .ok_or_else(|| ApiError::Unauthorized("Signin required.".to_string()))
 }
 
-pub fn get_conn_id(request: &HttpRequest) -> Result<ConnId, error::Error> {
-    if let Some(conn_id) = request.headers().get("Conn-Id") {
-        if let Ok(v) = conn_id.to_str() {
-            let conn_id = v.parse::<ConnId>();
-            if conn_id.is_err() {
-                return Err(error::ErrorBadRequest(json!({
-                      "message": "Invalid Conn-Id.",
-                })));
-            }
-            return Ok(conn_id.unwrap());
-        } else {
-            return Err(error::ErrorBadRequest(json!({
-                  "message": "Invalid Conn-Id.",
-            })));
-        }
-    } else {
-        return Err(error::ErrorBadRequest(json!({
-              "message": "Conn-Id not found in header.",
-        })));
-    }
+/// IP address of the connected peer, without trusting any forwarding headers.
+pub fn get_client_ip(request: &HttpRequest) -> String {
+    request
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default()
+}
+
+pub fn get_conn_id(request: &HttpRequest) -> Result<ConnId, ApiError> {
+    let Some(conn_id) = request.headers().get("Conn-Id") else {
+        return Err(ApiError::BadRequest(
+            "Conn-Id not found in header.".to_string(),
+        ));
+    };
+
+    conn_id
+        .to_str()
+        .ok()
+        .and_then(|conn_id| conn_id.parse::<ConnId>().ok())
+        .ok_or_else(|| ApiError::BadRequest("Invalid Conn-Id.".to_string()))
 }